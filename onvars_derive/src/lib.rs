@@ -0,0 +1,182 @@
+// #[derive(SaveStateable)] for onvars_tool.
+//
+// Every field needs an #[at(offset)] attribute giving where it lives
+// relative to the struct's address:
+//
+//   #[derive(SaveStateable)]
+//   struct ActionStruct {
+//       #[at(0x0)]
+//       data: [u8;0x30],
+//       #[at(0x2c, pointer)]
+//       collision_data: CollisionData,
+//   }
+//
+// Fields are saved, loaded and serialized in declaration order. With
+// `pointer`, the offset is where a 32-bit pointer to the field's data is
+// stored, and the field is saved/loaded wherever that points. Named
+// fields get their name added to the error path.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt, Token};
+
+struct At {
+    offset: LitInt,
+    pointer: bool,
+}
+
+impl Parse for At {
+    fn parse(input: ParseStream) -> syn::Result<At> {
+        let offset = input.parse()?;
+        let mut pointer = false;
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            let flag: Ident = input.parse()?;
+            if flag != "pointer" {
+                return Err(syn::Error::new(flag.span(), "expected `pointer`"));
+            }
+            pointer = true;
+        }
+        Ok(At { offset, pointer })
+    }
+}
+
+struct FieldInfo {
+    access: TokenStream2,
+    name: Option<String>,
+    at: At,
+}
+
+#[proc_macro_derive(SaveStateable, attributes(at))]
+pub fn derive_save_stateable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new(input.ident.span(), "SaveStateable can only be derived for structs")),
+    };
+
+    let mut infos = Vec::new();
+    match *fields {
+        Fields::Named(ref named) => {
+            for field in named.named.iter() {
+                let ident = field.ident.clone().unwrap();
+                infos.push(FieldInfo {
+                    access: quote!(#ident),
+                    name: Some(ident.to_string()),
+                    at: field_at(field)?,
+                });
+            }
+        }
+        Fields::Unnamed(ref unnamed) => {
+            for (idx, field) in unnamed.unnamed.iter().enumerate() {
+                let index = syn::Index::from(idx);
+                infos.push(FieldInfo {
+                    access: quote!(#index),
+                    name: None,
+                    at: field_at(field)?,
+                });
+            }
+        }
+        Fields::Unit => {}
+    }
+
+    let saves = infos.iter().map(|info| {
+        let access = &info.access;
+        let address = field_address(info);
+        with_context(info, quote! {
+            (|| -> ::core::result::Result<(), ::onvars_tool::error::Error> {
+                ::onvars_tool::SaveStateable::save(&mut self.#access, handle, #address)
+            })()
+        })
+    });
+    let loads = infos.iter().map(|info| {
+        let access = &info.access;
+        let address = field_address(info);
+        with_context(info, quote! {
+            (|| -> ::core::result::Result<(), ::onvars_tool::error::Error> {
+                ::onvars_tool::SaveStateable::load(&self.#access, handle, #address)
+            })()
+        })
+    });
+    let writes = infos.iter().map(|info| {
+        let access = &info.access;
+        quote!(::onvars_tool::SaveStateable::write_state(&self.#access, out);)
+    });
+    let reads = infos.iter().map(|info| {
+        let access = &info.access;
+        quote!(::onvars_tool::SaveStateable::read_state(&mut self.#access, input)?;)
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::onvars_tool::SaveStateable for #name #ty_generics #where_clause {
+            fn save(&mut self, handle: &dyn ::onvars_tool::GameMemory, address: u64)
+                -> ::core::result::Result<(), ::onvars_tool::error::Error>
+            {
+                #(#saves)*
+                Ok(())
+            }
+
+            fn load(&self, handle: &dyn ::onvars_tool::GameMemory, address: u64)
+                -> ::core::result::Result<(), ::onvars_tool::error::Error>
+            {
+                #(#loads)*
+                Ok(())
+            }
+
+            fn write_state(&self, out: &mut ::std::vec::Vec<u8>) {
+                #(#writes)*
+            }
+
+            fn read_state(&mut self, input: &mut ::onvars_tool::state_file::StateReader)
+                -> ::core::result::Result<(), ::onvars_tool::error::Error>
+            {
+                #(#reads)*
+                Ok(())
+            }
+        }
+    })
+}
+
+fn field_at(field: &syn::Field) -> syn::Result<At> {
+    let mut found = None;
+    for attr in field.attrs.iter() {
+        if attr.path().is_ident("at") {
+            if found.is_some() {
+                return Err(syn::Error::new(attr.span(), "duplicate #[at] attribute"));
+            }
+            found = Some(attr.parse_args::<At>()?);
+        }
+    }
+    found.ok_or_else(|| syn::Error::new(field.span(), "field needs an #[at(offset)] attribute"))
+}
+
+// Where the field's data is, given the struct's `address`.
+fn field_address(info: &FieldInfo) -> TokenStream2 {
+    let offset = &info.at.offset;
+    if info.at.pointer {
+        quote!(::onvars_tool::read_pointer(handle, address + #offset)?)
+    } else {
+        quote!(address + #offset)
+    }
+}
+
+fn with_context(info: &FieldInfo, call: TokenStream2) -> TokenStream2 {
+    match info.name {
+        Some(ref name) => quote!(::onvars_tool::error::ResultExt::context(#call, #name)?;),
+        None => quote!(#call?;),
+    }
+}
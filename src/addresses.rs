@@ -0,0 +1,220 @@
+// Where everything lives in game memory, for each game build.
+//
+// Addresses are read from a text file with a section per build:
+//
+//   # comments start with '#'
+//   [default]
+//   character_tasks = 0x01dea6e0
+//   camera = 0x01dcff00
+//   ...
+//
+//   [1.0 patched]
+//   header_crc = 0x1234abcd
+//   score = 0x0174B060
+//
+// The `default` section has every symbol, and other sections only list
+// the ones that are different. A section with a header_crc is for the
+// build with that header CRC (see build.rs), and having one is what makes
+// a build known. Addresses are decimal, or hex starting with 0x.
+//
+// Symbols ending in _size or _max are the sizes of the game's structs and
+// tables rather than addresses.
+//
+// Some symbols are optional, for things nobody has found in every build
+// yet. Leaving them out turns off whatever uses them. cutscene_mode is one
+// of these, and is the value at game_mode while an in-stage cutscene plays
+// rather than an address.
+//
+// The built-in database is addresses.cfg next to this file. A user's file
+// is merged on top of it: its sections are added, or replace single
+// symbols in the built-in section with the same name.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::parse_number;
+use crate::error::Error;
+
+pub const DEFAULT_ADDRESSES: &str = include_str!("addresses.cfg");
+
+pub const DEFAULT_SECTION: &str = "default";
+
+macro_rules! addresses {
+    ($($symbol:ident),* $(,)?; optional: $($optional:ident),* $(,)?) => {
+        // Every address the units and the tool itself use.
+        #[derive(Clone,Copy,Debug,PartialEq,Eq)]
+        pub struct Addresses {
+            $(pub $symbol: u64,)*
+            $(pub $optional: Option<u64>,)*
+        }
+
+        pub const SYMBOLS: &[&str] = &[$(stringify!($symbol),)* $(stringify!($optional)),*];
+
+        impl Addresses {
+            fn from_symbols(symbols: &BTreeMap<&'static str, u64>) -> Result<Addresses, Error> {
+                Ok(Addresses {
+                    $($symbol: *symbols.get(stringify!($symbol))
+                        .ok_or(Error::MissingAddress { symbol: stringify!($symbol) })?,)*
+                    $($optional: symbols.get(stringify!($optional)).cloned(),)*
+                })
+            }
+        }
+    };
+}
+
+addresses! {
+    character_tasks,
+    camera,
+    camera_elements,
+    past_positions_ptr,
+    past_rotations_ptr,
+    past_positions_idx,
+    camera_cons_past_positions,
+    camera_cons_past_positions_idx,
+    time,
+    gravity,
+    lives,
+    rings,
+    score,
+    rng_seed,
+    dyncol_list,
+    dyncol_count,
+    object_lists,
+    buttons,
+    frame_counter,
+    level,
+    game_state,
+    camera_size,
+    camera_element_size,
+    past_position_table_size,
+    object_data_size,
+    dyncol_max;
+    optional:
+    game_mode,
+    cutscene_mode,
+}
+
+impl Default for Addresses {
+    fn default() -> Addresses {
+        AddressDatabase::default().addresses(DEFAULT_SECTION).unwrap()
+    }
+}
+
+fn symbol_from_name(name: &str) -> Option<&'static str> {
+    SYMBOLS.iter().find(|&&symbol| symbol == name).cloned()
+}
+
+struct Section {
+    name: String,
+    header_crc: Option<u32>,
+    symbols: BTreeMap<&'static str, u64>,
+}
+
+pub struct AddressDatabase {
+    sections: Vec<Section>,
+}
+
+impl AddressDatabase {
+    pub fn parse(text: &str) -> Result<AddressDatabase, Error> {
+        let mut sections: Vec<Section> = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidConfig { line: idx + 1, reason };
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(invalid("expected \"[section]\""));
+                }
+                let name = line[1..line.len() - 1].trim();
+                if name.is_empty() {
+                    return Err(invalid("empty section name"));
+                }
+                if sections.iter().any(|section| section.name == name) {
+                    return Err(invalid("duplicate section"));
+                }
+                sections.push(Section {
+                    name: name.to_string(),
+                    header_crc: None,
+                    symbols: BTreeMap::new(),
+                });
+                continue;
+            }
+
+            let section = sections.last_mut().ok_or_else(|| invalid("expected a [section] first"))?;
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let value = parts.next().ok_or_else(|| invalid("expected \"symbol = address\""))?;
+            let value = parse_number(value.trim()).ok_or_else(|| invalid("address is not a number"))?;
+            if name == "header_crc" {
+                if section.name == DEFAULT_SECTION {
+                    return Err(invalid("the default section can't have a header_crc"));
+                }
+                if value > u32::MAX as u64 {
+                    return Err(invalid("header_crc is more than 32 bits"));
+                }
+                section.header_crc = Some(value as u32);
+            } else {
+                let symbol = symbol_from_name(name).ok_or_else(|| invalid("unknown symbol"))?;
+                if section.symbols.insert(symbol, value).is_some() {
+                    return Err(invalid("duplicate symbol"));
+                }
+            }
+        }
+        Ok(AddressDatabase {
+            sections,
+        })
+    }
+
+    pub fn read_file(path: &Path) -> Result<AddressDatabase, Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Io { operation: "reading addresses file", code: e.raw_os_error() })?;
+        AddressDatabase::parse(&text)
+    }
+
+    // Adds `other`'s sections, with its symbols taking priority over ours
+    // where both have a section with the same name.
+    pub fn merge(&mut self, other: AddressDatabase) {
+        for section in other.sections {
+            match self.sections.iter_mut().find(|ours| ours.name == section.name) {
+                Some(ours) => {
+                    if section.header_crc.is_some() {
+                        ours.header_crc = section.header_crc;
+                    }
+                    ours.symbols.extend(section.symbols);
+                }
+                None => self.sections.push(section),
+            }
+        }
+    }
+
+    // The name of the section for the build with this header CRC, if
+    // there is one.
+    pub fn build_name(&self, header_crc: u32) -> Option<&str> {
+        self.sections.iter()
+            .find(|section| section.header_crc == Some(header_crc))
+            .map(|section| section.name.as_str())
+    }
+
+    // Every address for the section called `name`, with anything it
+    // doesn't list taken from the default section. A section that doesn't
+    // exist just gets the defaults.
+    pub fn addresses(&self, name: &str) -> Result<Addresses, Error> {
+        let mut symbols = BTreeMap::new();
+        for section_name in [DEFAULT_SECTION, name].iter() {
+            if let Some(section) = self.sections.iter().find(|section| section.name == *section_name) {
+                symbols.extend(section.symbols.iter().map(|(&symbol, &value)| (symbol, value)));
+            }
+        }
+        Addresses::from_symbols(&symbols)
+    }
+}
+
+impl Default for AddressDatabase {
+    fn default() -> AddressDatabase {
+        AddressDatabase::parse(DEFAULT_ADDRESSES).unwrap()
+    }
+}
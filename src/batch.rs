@@ -0,0 +1,229 @@
+// Doing a whole save or load in as few transfers as possible.
+//
+// Every read_data/write_data on the game is a syscall, and a save walks
+// a lot of small pieces of memory. Batch sits in front of a backend for
+// one save or load:
+//
+// - Reads are served from a cache of whole pages. A read that isn't cached
+//   fetches every page it touches in one transfer, so pointers and fields
+//   near each other cost one syscall between them.
+// - Units declare the memory at fixed addresses they use (see
+//   SaveStateUnit::regions), and it's all fetched up front, with runs of
+//   neighboring pages read together.
+// - Writes are held back and made by flush(), with writes that touch or
+//   overlap merged into one. Reads see the held-back writes.
+//
+// Memory is mapped a page at a time, so reading the rest of a page that's
+// being read anyway can't fail where the original read wouldn't. Backends
+// that don't line up with pages (memory images made of odd-sized regions)
+// still work: when a page fetch fails, the read goes straight to the
+// backend instead, and fails or comes up short exactly as it would have.
+//
+// Writes aren't merged across gaps, since that would mean writing back
+// bytes the game might have changed since we read them.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::{ByteOrder, GameMemory, SaveStateUnit};
+use crate::error::Error;
+
+// x86 pages.
+pub const PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct MemoryRange {
+    pub address: u64,
+    pub len: u64,
+}
+
+impl MemoryRange {
+    pub fn new(address: u64, len: u64) -> MemoryRange {
+        MemoryRange {
+            address,
+            len,
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.address.saturating_add(self.len)
+    }
+}
+
+// Sorts `ranges` and merges any that overlap or touch.
+fn merge(mut ranges: Vec<MemoryRange>) -> Vec<MemoryRange> {
+    ranges.sort_by_key(|range| range.address);
+    let mut merged: Vec<MemoryRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.address <= last.end() => {
+                last.len = last.len.max(range.end() - last.address);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// The pages `ranges` touch, as runs of neighboring pages.
+pub fn coalesce_pages(ranges: &[MemoryRange]) -> Vec<MemoryRange> {
+    merge(ranges.iter()
+        .filter(|range| range.len != 0)
+        .map(|range| {
+            let start = range.address / PAGE_SIZE * PAGE_SIZE;
+            let end = range.end().saturating_add(PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+            MemoryRange::new(start, end - start)
+        })
+        .collect())
+}
+
+// Copies the part of `data` (at `address`) that overlaps `target` (at
+// `target_address`) into it.
+pub(crate) fn copy_overlap(address: u64, data: &[u8], target_address: u64, target: &mut [u8]) {
+    let start = address.max(target_address);
+    let end = (address + data.len() as u64).min(target_address + target.len() as u64);
+    if start < end {
+        let len = (end - start) as usize;
+        let from = (start - address) as usize;
+        let to = (start - target_address) as usize;
+        target[to..to + len].copy_from_slice(&data[from..from + len]);
+    }
+}
+
+// Merges writes that overlap or touch. Where writes overlap, the later
+// one wins.
+pub fn coalesce_writes(writes: &[(u64, Vec<u8>)]) -> Vec<(u64, Vec<u8>)> {
+    let spans = merge(writes.iter()
+        .filter(|(_, data)| !data.is_empty())
+        .map(|(address, data)| MemoryRange::new(*address, data.len() as u64))
+        .collect());
+    spans.iter()
+        .map(|span| {
+            let mut data = vec![0; span.len as usize];
+            for (address, write) in writes.iter() {
+                copy_overlap(*address, write, span.address, &mut data);
+            }
+            (span.address, data)
+        })
+        .collect()
+}
+
+// Every region `units` declare.
+pub fn unit_regions(units: &[&dyn SaveStateUnit]) -> Vec<MemoryRange> {
+    units.iter().flat_map(|unit| unit.regions()).collect()
+}
+
+pub struct Batch<'a> {
+    inner: &'a dyn GameMemory,
+    // Cached pages, by page number.
+    pages: RefCell<BTreeMap<u64, Box<[u8]>>>,
+    // Writes that haven't been made yet, in the order they came in.
+    writes: RefCell<Vec<(u64, Vec<u8>)>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(inner: &'a dyn GameMemory) -> Batch<'a> {
+        Batch {
+            inner,
+            pages: RefCell::new(BTreeMap::new()),
+            writes: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Fetches the pages `ranges` touch. Pages that can't be read are
+    // skipped; reads from them go straight to the backend later.
+    pub fn prefetch(&self, ranges: &[MemoryRange]) {
+        for run in coalesce_pages(ranges) {
+            self.fetch(run);
+        }
+    }
+
+    // Caches every page in `run` (a page-aligned range) that isn't
+    // already, one transfer per run of missing pages. Returns whether the
+    // whole of `run` is cached now.
+    fn fetch(&self, run: MemoryRange) -> bool {
+        let first = run.address / PAGE_SIZE;
+        let count = run.len / PAGE_SIZE;
+        let missing: Vec<MemoryRange> = {
+            let pages = self.pages.borrow();
+            merge((first..first + count)
+                .filter(|page| !pages.contains_key(page))
+                .map(|page| MemoryRange::new(page * PAGE_SIZE, PAGE_SIZE))
+                .collect())
+        };
+
+        let mut complete = true;
+        for range in missing {
+            let mut data = vec![0; range.len as usize];
+            match self.inner.read_data(range.address, &mut data) {
+                Ok(read) if read == data.len() => {}
+                _ => {
+                    complete = false;
+                    continue;
+                }
+            }
+            for (address, write) in self.writes.borrow().iter() {
+                copy_overlap(*address, write, range.address, &mut data);
+            }
+            let mut pages = self.pages.borrow_mut();
+            for (idx, page) in data.chunks(PAGE_SIZE as usize).enumerate() {
+                pages.insert(range.address / PAGE_SIZE + idx as u64, page.into());
+            }
+        }
+        complete
+    }
+
+    // Makes every held-back write, merged. Stops at the first one that
+    // fails.
+    pub fn flush(&self) -> Result<(), Error> {
+        let writes = self.writes.replace(Vec::new());
+        for (address, data) in coalesce_writes(&writes) {
+            self.inner.write_all(address, &data)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> GameMemory for Batch<'a> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let range = MemoryRange::new(address, buf.len() as u64);
+        if coalesce_pages(&[range]).into_iter().all(|run| self.fetch(run)) {
+            let pages = self.pages.borrow();
+            for (&page, data) in pages.range(address / PAGE_SIZE..=(range.end() - 1) / PAGE_SIZE) {
+                copy_overlap(page * PAGE_SIZE, data, address, buf);
+            }
+            return Ok(buf.len());
+        }
+
+        let read = self.inner.read_data(address, buf)?;
+        for (write_address, write) in self.writes.borrow().iter() {
+            copy_overlap(*write_address, write, address, &mut buf[..read]);
+        }
+        Ok(read)
+    }
+
+    // Always succeeds; failures show up in flush().
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let range = MemoryRange::new(address, buf.len() as u64);
+        let mut pages = self.pages.borrow_mut();
+        for (&page, data) in pages.range_mut(address / PAGE_SIZE..=(range.end() - 1) / PAGE_SIZE) {
+            copy_overlap(address, buf, page * PAGE_SIZE, data);
+        }
+        self.writes.borrow_mut().push((address, buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        self.inner.byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        self.inner.pointer_size()
+    }
+}
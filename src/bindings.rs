@@ -0,0 +1,247 @@
+// Controller bindings for the tool's actions.
+//
+// Bindings are read from a text file with one binding per line:
+//
+//   # comments start with '#'
+//   save = Left
+//   load = Right
+//   next_slot = R+Up
+//   prev_slot = R+Down
+//   save = Y+B hold 30
+//
+// A binding is one or more button names joined with '+'. All of them have
+// to be held, and the action fires when the last one goes down. Adding
+// "hold N" makes the action fire once the buttons have been held for N
+// frames instead. An action can have more than one binding.
+//
+// When two bindings fire at once and one's buttons are a subset of the
+// other's, only the larger chord counts, so "R+Up" doesn't also trigger
+// a plain "Up" binding.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+
+// Names for the bits of the button word (the buttons address).
+// Bits without a name can still be bound as Bit0 through Bit31.
+pub const BUTTON_NAMES: [(&str, u32); 12] = [
+    ("Left", 0x1),
+    ("Right", 0x2),
+    ("Down", 0x4),
+    ("Up", 0x8),
+    ("Z", 0x10),
+    ("R", 0x20),
+    ("L", 0x40),
+    ("A", 0x100),
+    ("B", 0x200),
+    ("X", 0x400),
+    ("Y", 0x800),
+    ("Start", 0x1000),
+];
+
+pub const DEFAULT_BINDINGS: &str = "\
+save = Left
+load = Right
+next_slot = R+Up
+prev_slot = R+Down
+";
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Action {
+    Save,
+    Load,
+    NextSlot,
+    PrevSlot,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "save" => Some(Action::Save),
+            "load" => Some(Action::Load),
+            "next_slot" => Some(Action::NextSlot),
+            "prev_slot" => Some(Action::PrevSlot),
+            _ => None,
+        }
+    }
+}
+
+pub fn button_from_name(name: &str) -> Option<u32> {
+    for &(button_name, mask) in BUTTON_NAMES.iter() {
+        if button_name.eq_ignore_ascii_case(name) {
+            return Some(mask);
+        }
+    }
+    // get() so a name that isn't ASCII can't be cut mid-character.
+    if name.len() > 3 && name.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("bit")) {
+        let bit: u32 = name[3..].parse().ok()?;
+        if bit < 32 {
+            return Some(1 << bit);
+        }
+    }
+    None
+}
+
+pub fn button_name(mask: u32) -> String {
+    for &(name, button_mask) in BUTTON_NAMES.iter() {
+        if button_mask == mask {
+            return name.to_string();
+        }
+    }
+    format!("Bit{}", mask.trailing_zeros())
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Binding {
+    pub buttons: u32,
+    pub hold_frames: u32,
+}
+
+impl Binding {
+    pub fn parse(text: &str) -> Result<Binding, &'static str> {
+        let mut words = text.split_whitespace();
+        let chord = words.next().ok_or("missing buttons")?;
+        let mut buttons = 0;
+        for name in chord.split('+') {
+            buttons |= button_from_name(name).ok_or("unknown button name")?;
+        }
+
+        let hold_frames = match words.next() {
+            None => 0,
+            Some(word) if word.eq_ignore_ascii_case("hold") => {
+                let frames = words.next()
+                    .ok_or("missing frame count after \"hold\"")?
+                    .parse()
+                    .map_err(|_| "frame count is not a number")?;
+                if frames == 0 {
+                    return Err("frame count must be at least 1");
+                }
+                frames
+            }
+            Some(_) => return Err("expected \"hold\" after buttons"),
+        };
+        if words.next().is_some() {
+            return Err("unexpected text after binding");
+        }
+
+        Ok(Binding {
+            buttons,
+            hold_frames,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let names: Vec<String> = (0..32)
+            .map(|bit| 1 << bit)
+            .filter(|mask| self.buttons & mask != 0)
+            .map(button_name)
+            .collect();
+        let mut text = names.join("+");
+        if self.hold_frames != 0 {
+            text.push_str(&format!(" (hold {} frames)", self.hold_frames));
+        }
+        text
+    }
+}
+
+struct BindingState {
+    action: Action,
+    binding: Binding,
+    hold_start: Option<u32>,
+    fired: bool,
+}
+
+pub struct Bindings {
+    entries: Vec<BindingState>,
+    prev_buttons: u32,
+}
+
+impl Bindings {
+    pub fn parse(text: &str) -> Result<Bindings, Error> {
+        let mut entries = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidConfig { line: idx + 1, reason };
+
+            let mut parts = line.splitn(2, '=');
+            let action_name = parts.next().unwrap().trim();
+            let binding_text = parts.next().ok_or_else(|| invalid("expected \"action = buttons\""))?;
+            let action = Action::from_name(action_name).ok_or_else(|| invalid("unknown action"))?;
+            let binding = Binding::parse(binding_text).map_err(invalid)?;
+            entries.push(BindingState {
+                action,
+                binding,
+                hold_start: None,
+                fired: false,
+            });
+        }
+        Ok(Bindings {
+            entries,
+            prev_buttons: 0,
+        })
+    }
+
+    pub fn read_file(path: &Path) -> Result<Bindings, Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Io { operation: "reading bindings file", code: e.raw_os_error() })?;
+        Bindings::parse(&text)
+    }
+
+    pub fn bindings_for(&self, action: Action) -> Vec<Binding> {
+        self.entries.iter()
+            .filter(|entry| entry.action == action)
+            .map(|entry| entry.binding)
+            .collect()
+    }
+
+    // Feeds in the current button word and the game's frame counter, and
+    // returns the actions that fired since the last update.
+    pub fn update(&mut self, buttons: u32, frame: u32) -> Vec<Action> {
+        let pressed = !self.prev_buttons & buttons;
+        self.prev_buttons = buttons;
+
+        let mut fired: Vec<(Action, u32)> = Vec::new();
+        for entry in self.entries.iter_mut() {
+            let mask = entry.binding.buttons;
+            let held = buttons & mask == mask;
+            if !held {
+                entry.hold_start = None;
+                entry.fired = false;
+                continue;
+            }
+
+            if entry.binding.hold_frames == 0 {
+                if pressed & mask != 0 {
+                    fired.push((entry.action, mask));
+                }
+            } else {
+                let start = *entry.hold_start.get_or_insert(frame);
+                if !entry.fired && frame.wrapping_sub(start) >= entry.binding.hold_frames {
+                    entry.fired = true;
+                    fired.push((entry.action, mask));
+                }
+            }
+        }
+
+        // Drop anything that's only firing because it's part of a bigger chord.
+        let masks: Vec<u32> = fired.iter().map(|&(_, mask)| mask).collect();
+        let mut actions = Vec::new();
+        for (action, mask) in fired {
+            let shadowed = masks.iter().any(|&other| other != mask && other & mask == mask);
+            if !shadowed && !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::parse(DEFAULT_BINDINGS).unwrap()
+    }
+}
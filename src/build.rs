@@ -0,0 +1,79 @@
+// Working out which build of the game we're attached to.
+//
+// Every address the tool uses is for one particular build, and writing
+// them into any other build would corrupt it. A PC build is identified by a
+// CRC-32 of sonic2app.exe's PE headers, which covers the link timestamp,
+// checksum and section layout. The address database (see addresses.rs)
+// says which builds are known. Unknown builds should only get read-only
+// access (see ReadOnly).
+
+use crate::{ByteOrder, GameMemory};
+use crate::error::{Error, ResultExt};
+use crate::state_file::crc32;
+
+// sonic2app.exe isn't relocated.
+pub const IMAGE_BASE: u64 = 0x400000;
+
+// More than any real header, so a garbage SizeOfHeaders can't make us read
+// megabytes.
+const MAX_HEADER_SIZE: u32 = 0x1000;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct BuildInfo {
+    // PE TimeDateStamp, which state_id() records in savestate files.
+    pub timestamp: u32,
+    pub header_crc: u32,
+}
+
+impl BuildInfo {
+    pub fn detect(handle: &dyn GameMemory) -> Result<BuildInfo, Error> {
+        let pe_offset = handle.read_u32(IMAGE_BASE + 0x3c).context("build")? as u64;
+        let timestamp = handle.read_u32(IMAGE_BASE + pe_offset + 0x8).context("build")?;
+        // SizeOfHeaders, in the optional header after the PE signature and
+        // file header.
+        let header_size = handle.read_u32(IMAGE_BASE + pe_offset + 0x54).context("build")?;
+        if header_size == 0 || header_size > MAX_HEADER_SIZE || (header_size as u64) < pe_offset + 0x58 {
+            return Err(Error::InvalidBuild { reason: "PE header size out of range" });
+        }
+
+        let mut headers = vec![0; header_size as usize];
+        handle.read_exact(IMAGE_BASE, &mut headers).context("build")?;
+        Ok(BuildInfo {
+            timestamp,
+            header_crc: crc32(&headers),
+        })
+    }
+
+    // What savestate files record as the build. That's always been the
+    // timestamp.
+    pub fn state_id(&self) -> u32 {
+        self.timestamp
+    }
+}
+
+// Passes reads through and refuses every write.
+pub struct ReadOnly<M>(M);
+
+impl<M: GameMemory> ReadOnly<M> {
+    pub fn new(inner: M) -> ReadOnly<M> {
+        ReadOnly(inner)
+    }
+}
+
+impl<M: GameMemory> GameMemory for ReadOnly<M> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.read_data(address, buf)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        Err(Error::ReadOnly { address, requested: buf.len() })
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        self.0.byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        self.0.pointer_size()
+    }
+}
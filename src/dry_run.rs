@@ -0,0 +1,134 @@
+// Trying out loads without touching the game.
+//
+// DryRun wraps a backend and, instead of writing, notes down what each
+// write would have changed: the address, the bytes there before and the
+// bytes that would have gone in. Reads see the noted writes, so a load
+// that reads back what it wrote behaves as it would for real. Writes
+// only fail if the memory can't be read, since writing there would have
+// failed too.
+
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+
+use crate::{ByteOrder, GameMemory};
+use crate::batch::copy_overlap;
+use crate::error::{AccessKind, Error};
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct LoggedWrite {
+    pub address: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl LoggedWrite {
+    pub fn changed(&self) -> usize {
+        self.old.iter().zip(self.new.iter()).filter(|(old, new)| old != new).count()
+    }
+}
+
+// Bytes per line of a diff.
+const DIFF_ROW: usize = 16;
+
+fn write_row(out: &mut String, sign: char, address: u64, bytes: &[u8]) {
+    write!(out, "  {} {:#010x} ", sign, address).unwrap();
+    for byte in bytes {
+        write!(out, " {:02x}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+// Lists each write, with the rows of bytes it would change: old ones
+// marked "-" and new ones "+".
+pub fn diff(writes: &[LoggedWrite]) -> String {
+    let mut out = String::new();
+    for write in writes.iter() {
+        let changed = write.changed();
+        if changed == 0 {
+            writeln!(out, "{:#010x}: {:#x} bytes, unchanged", write.address, write.new.len()).unwrap();
+            continue;
+        }
+        writeln!(out, "{:#010x}: {:#x} bytes, {:#x} changed", write.address, write.new.len(), changed).unwrap();
+        for (row, (old, new)) in write.old.chunks(DIFF_ROW).zip(write.new.chunks(DIFF_ROW)).enumerate() {
+            if old != new {
+                let address = write.address + (row * DIFF_ROW) as u64;
+                write_row(&mut out, '-', address, old);
+                write_row(&mut out, '+', address, new);
+            }
+        }
+    }
+    out
+}
+
+pub struct DryRun<M> {
+    inner: M,
+    writes: RefCell<Vec<LoggedWrite>>,
+    // How many of `writes` new_writes() has already handed out.
+    reported: Cell<usize>,
+}
+
+impl<M: GameMemory> DryRun<M> {
+    pub fn new(inner: M) -> DryRun<M> {
+        DryRun {
+            inner,
+            writes: RefCell::new(Vec::new()),
+            reported: Cell::new(0),
+        }
+    }
+
+    // The writes so far, in order. After this, reads see the game's
+    // memory as it really is again.
+    pub fn take_writes(&self) -> Vec<LoggedWrite> {
+        self.reported.set(0);
+        self.writes.replace(Vec::new())
+    }
+
+    // The writes since the last call, in order. Reads keep seeing them, so
+    // a load spread over several frames sees what its earlier passes
+    // wrote.
+    pub fn new_writes(&self) -> Vec<LoggedWrite> {
+        let writes = self.writes.borrow();
+        let new = writes[self.reported.get()..].to_vec();
+        self.reported.set(writes.len());
+        new
+    }
+
+    // Forgets the writes, once nothing needs to read them back.
+    pub fn clear(&self) {
+        self.take_writes();
+    }
+}
+
+impl<M: GameMemory> GameMemory for DryRun<M> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.inner.read_data(address, buf)?;
+        for write in self.writes.borrow().iter() {
+            copy_overlap(write.address, &write.new, address, &mut buf[..read]);
+        }
+        Ok(read)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        let mut old = vec![0; buf.len()];
+        self.read_exact(address, &mut old).map_err(|err| Error::Access {
+            kind: AccessKind::Write,
+            address,
+            requested: buf.len(),
+            code: err.os_code(),
+        })?;
+        self.writes.borrow_mut().push(LoggedWrite {
+            address,
+            old,
+            new: buf.to_vec(),
+        });
+        Ok(buf.len())
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        self.inner.byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        self.inner.pointer_size()
+    }
+}
@@ -0,0 +1,241 @@
+use std::error;
+use std::fmt;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Error {
+    // Finding, opening or querying the game process failed.
+    // `code` is the OS error code, if there was one.
+    Process {
+        operation: &'static str,
+        code: Option<i32>,
+    },
+    // A read or write of game memory failed outright.
+    Access {
+        kind: AccessKind,
+        address: u64,
+        requested: usize,
+        code: Option<i32>,
+    },
+    // A read or write of game memory only transferred some of the bytes.
+    PartialAccess {
+        kind: AccessKind,
+        address: u64,
+        requested: usize,
+        transferred: usize,
+    },
+    // The pointer stored at `address` was null.
+    NullPointer {
+        address: u64,
+    },
+    // The character in game isn't the one the savestate was made with.
+    CharacterMismatch {
+        saved: u8,
+        current: u8,
+    },
+    // A player slot has a different character (or is empty/filled) compared
+    // to when the savestate was made. Players are numbered from 1.
+    PlayerMismatch {
+        player: usize,
+        saved: Option<u8>,
+        current: Option<u8>,
+    },
+    // We don't know the physics layout for this character.
+    UnsupportedCharacter {
+        character_id: u8,
+    },
+    // Reading or writing a savestate file failed.
+    Io {
+        operation: &'static str,
+        code: Option<i32>,
+    },
+    // A savestate file is corrupt or isn't a savestate at all.
+    InvalidSavestate {
+        reason: &'static str,
+    },
+    // A savestate file was written by a newer (or much older) version.
+    UnsupportedVersion {
+        version: u16,
+    },
+    // A line in a config file couldn't be understood.
+    InvalidConfig {
+        line: usize,
+        reason: &'static str,
+    },
+    // The address database doesn't have an address for this symbol.
+    MissingAddress {
+        symbol: &'static str,
+    },
+    // The game's main module doesn't look like a PE image we can identify.
+    InvalidBuild {
+        reason: &'static str,
+    },
+    // A memory image (minidump or region dump) couldn't be understood.
+    InvalidImage {
+        reason: &'static str,
+    },
+    // Writes are turned off, because the game build isn't a known one or
+    // the memory is only a snapshot.
+    ReadOnly {
+        address: u64,
+        requested: usize,
+    },
+    // A replayed call wasn't the one in the trace. Entries are numbered
+    // from 1.
+    TraceMismatch {
+        entry: usize,
+        reason: &'static str,
+    },
+    // Another error, along with the unit/struct path we were in when it
+    // happened (outermost first).
+    Context {
+        path: Vec<&'static str>,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    // Tags the error with the name of the unit/struct/field it came from.
+    pub fn context(self, name: &'static str) -> Error {
+        match self {
+            Error::Context { mut path, source } => {
+                path.insert(0, name);
+                Error::Context { path, source }
+            }
+            other => Error::Context {
+                path: vec![name],
+                source: Box::new(other),
+            },
+        }
+    }
+
+    // The error without any context attached.
+    pub fn root(&self) -> &Error {
+        match *self {
+            Error::Context { ref source, .. } => source.root(),
+            ref other => other,
+        }
+    }
+
+    // The unit/struct path the error happened in, if it was tagged with one.
+    pub fn path(&self) -> &[&'static str] {
+        match *self {
+            Error::Context { ref path, .. } => path,
+            _ => &[],
+        }
+    }
+
+    // The OS error code behind this error, if there is one.
+    pub fn os_code(&self) -> Option<i32> {
+        match *self.root() {
+            Error::Process { code, .. } | Error::Access { code, .. } | Error::Io { code, .. } => code,
+            _ => None,
+        }
+    }
+}
+
+fn write_os_code(f: &mut fmt::Formatter, code: Option<i32>) -> fmt::Result {
+    if let Some(code) = code {
+        write!(f, " (os error {})", code)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Process { operation, code } => {
+                write!(f, "{} failed", operation)?;
+                write_os_code(f, code)
+            }
+            Error::Access { kind, address, requested, code } => {
+                write!(f, "could not {} {:#x} bytes at {:#010x}", kind, requested, address)?;
+                write_os_code(f, code)
+            }
+            Error::PartialAccess { kind, address, requested, transferred } => {
+                write!(f, "partial {} at {:#010x}: {:#x} of {:#x} bytes", kind, address, transferred, requested)
+            }
+            Error::NullPointer { address } => {
+                write!(f, "null pointer at {:#010x}", address)
+            }
+            Error::CharacterMismatch { saved, current } => {
+                write!(f, "current character ({}) does not match savestate character ({})", current, saved)
+            }
+            Error::PlayerMismatch { player, saved, current } => {
+                let describe = |id: Option<u8>| match id {
+                    Some(id) => format!("character {}", id),
+                    None => "nobody".to_string(),
+                };
+                write!(f, "player {} is {} but savestate has {}", player, describe(current), describe(saved))
+            }
+            Error::UnsupportedCharacter { character_id } => {
+                write!(f, "character type {} not supported", character_id)
+            }
+            Error::Io { operation, code } => {
+                write!(f, "{} failed", operation)?;
+                write_os_code(f, code)
+            }
+            Error::InvalidSavestate { reason } => {
+                write!(f, "invalid savestate: {}", reason)
+            }
+            Error::UnsupportedVersion { version } => {
+                write!(f, "unsupported savestate format version {}", version)
+            }
+            Error::InvalidConfig { line, reason } => {
+                write!(f, "line {}: {}", line, reason)
+            }
+            Error::MissingAddress { symbol } => {
+                write!(f, "no address for \"{}\"", symbol)
+            }
+            Error::InvalidBuild { reason } => {
+                write!(f, "could not identify game build: {}", reason)
+            }
+            Error::InvalidImage { reason } => {
+                write!(f, "invalid memory image: {}", reason)
+            }
+            Error::ReadOnly { address, requested } => {
+                write!(f, "not writing {:#x} bytes at {:#010x}: memory is read-only", requested, address)
+            }
+            Error::TraceMismatch { entry, reason } => {
+                write!(f, "trace entry {} doesn't match: {}", entry, reason)
+            }
+            Error::Context { ref path, ref source } => {
+                write!(f, "{}: {}", path.join("."), source)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Context { ref source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
+
+// Lets us write `.context("name")` on results.
+pub trait ResultExt<T> {
+    fn context(self, name: &'static str) -> Result<T, Error>;
+}
+
+impl<T> ResultExt<T> for Result<T, Error> {
+    fn context(self, name: &'static str) -> Result<T, Error> {
+        self.map_err(|e| e.context(name))
+    }
+}
@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::GameMemory;
+use crate::error::{AccessKind, Error};
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+// A sparse, in-memory stand-in for the game's address space.
+// Memory is split into pages that have to be mapped before they can be
+// touched. Accessing anything outside a mapped page fails the same way
+// ReadProcessMemory/WriteProcessMemory would.
+#[derive(Clone, Debug, Default)]
+pub struct FakeMemory {
+    pages: RefCell<BTreeMap<u64, Box<[u8]>>>,
+}
+
+impl FakeMemory {
+    pub fn new() -> FakeMemory {
+        FakeMemory {
+            pages: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    // Maps zeroed pages covering [address, address + len).
+    // Pages that are already mapped keep their contents.
+    pub fn map(&self, address: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let mut pages = self.pages.borrow_mut();
+        let first_page = address / PAGE_SIZE;
+        let last_page = (address + len - 1) / PAGE_SIZE;
+        for page in first_page..=last_page {
+            pages.entry(page)
+                .or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+        }
+    }
+
+    // Unmaps every page that overlaps [address, address + len).
+    pub fn unmap(&self, address: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let mut pages = self.pages.borrow_mut();
+        let first_page = address / PAGE_SIZE;
+        let last_page = (address + len - 1) / PAGE_SIZE;
+        for page in first_page..=last_page {
+            pages.remove(&page);
+        }
+    }
+
+    pub fn is_mapped(&self, address: u64, len: u64) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let pages = self.pages.borrow();
+        let first_page = address / PAGE_SIZE;
+        let last_page = (address + len - 1) / PAGE_SIZE;
+        (first_page..=last_page).all(|page| pages.contains_key(&page))
+    }
+
+    // Maps the pages needed for `data` and copies it in.
+    pub fn seed(&self, address: u64, data: &[u8]) {
+        self.map(address, data.len() as u64);
+        self.copy_in(address, data);
+    }
+
+    pub fn seed_u32(&self, address: u64, value: u32) {
+        self.seed(address, &value.to_le_bytes());
+    }
+
+    // Convenience for tests. Panics if the range isn't mapped.
+    pub fn bytes(&self, address: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        assert!(self.is_mapped(address, len as u64), "reading unmapped fake memory at {:#x}", address);
+        self.copy_out(address, &mut buf);
+        buf
+    }
+
+    fn copy_in(&self, address: u64, data: &[u8]) {
+        let mut pages = self.pages.borrow_mut();
+        let mut offset = 0;
+        while offset < data.len() {
+            let addr = address + offset as u64;
+            let page_offset = (addr % PAGE_SIZE) as usize;
+            let chunk = (PAGE_SIZE as usize - page_offset).min(data.len() - offset);
+            let page = pages.get_mut(&(addr / PAGE_SIZE)).unwrap();
+            page[page_offset..page_offset + chunk].copy_from_slice(&data[offset..offset + chunk]);
+            offset += chunk;
+        }
+    }
+
+    fn copy_out(&self, address: u64, buf: &mut [u8]) {
+        let pages = self.pages.borrow();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let addr = address + offset as u64;
+            let page_offset = (addr % PAGE_SIZE) as usize;
+            let chunk = (PAGE_SIZE as usize - page_offset).min(buf.len() - offset);
+            let page = &pages[&(addr / PAGE_SIZE)];
+            buf[offset..offset + chunk].copy_from_slice(&page[page_offset..page_offset + chunk]);
+            offset += chunk;
+        }
+    }
+}
+
+impl GameMemory for FakeMemory {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        if !self.is_mapped(address, buf.len() as u64) {
+            return Err(Error::Access {
+                kind: AccessKind::Read,
+                address,
+                requested: buf.len(),
+                code: None,
+            });
+        }
+        self.copy_out(address, buf);
+        Ok(buf.len())
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        if !self.is_mapped(address, buf.len() as u64) {
+            return Err(Error::Access {
+                kind: AccessKind::Write,
+                address,
+                requested: buf.len(),
+                code: None,
+            });
+        }
+        self.copy_in(address, buf);
+        Ok(buf.len())
+    }
+}
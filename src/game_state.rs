@@ -0,0 +1,155 @@
+// What the game is doing, from the game state value at game_state and, if
+// the address database has it, the mode value at game_mode. Without
+// game_mode, StageClock tells cutscenes apart by the stage timer stopping.
+//
+// The state values follow the GameStates enum in the SA2 Mod Loader's
+// headers (SA2Enums.h). Values we don't know about come out as Unknown and
+// are treated as unsafe. Loading a state while the game is tearing the
+// stage down (dying, results screen, exiting), building it (loading) or
+// playing a cutscene in it can crash it or leave it in a broken state, so
+// only InLevel allows loads. Saving is fine while paused too, since
+// nothing's moving.
+
+use crate::GameMemory;
+use crate::addresses::Addresses;
+use crate::error::Error;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum GameState {
+    // Menus, or no game running.
+    Inactive,
+    // Loading a stage, or counting in to start it.
+    Loading,
+    InLevel,
+    Paused,
+    // A cutscene playing in the stage. Detected through game_mode and
+    // cutscene_mode when the address database has them, or else by
+    // StageClock.
+    Cutscene,
+    // Dead, and about to restart the stage.
+    Dying,
+    Restarting,
+    // Results screen, or leaving the stage some other way.
+    Exiting,
+    Unknown(u32),
+}
+
+impl GameState {
+    pub fn from_raw(value: u32) -> GameState {
+        match value {
+            0x0 => GameState::Inactive,
+            0x1 | 0x2 | 0x3 | 0xf => GameState::Loading,
+            0x4 | 0x5 | 0x6 | 0x9 | 0xa | 0xb | 0xd | 0xe => GameState::Exiting,
+            0x7 | 0x8 => GameState::Restarting,
+            0xc => GameState::Dying,
+            0x10 => GameState::InLevel,
+            0x11 => GameState::Paused,
+            other => GameState::Unknown(other),
+        }
+    }
+
+    // Like from_raw, but a cutscene in the stage is told apart by `mode`
+    // being `cutscene_mode`.
+    pub fn from_raw_with_mode(value: u32, mode: u32, cutscene_mode: u64) -> GameState {
+        match GameState::from_raw(value) {
+            GameState::InLevel if mode as u64 == cutscene_mode => GameState::Cutscene,
+            other => other,
+        }
+    }
+
+    pub fn read(handle: &dyn GameMemory, addresses: &Addresses) -> Result<GameState, Error> {
+        let value = handle.read_u32(addresses.game_state)?;
+        match (addresses.game_mode, addresses.cutscene_mode) {
+            (Some(game_mode), Some(cutscene_mode)) => {
+                let mode = handle.read_u32(game_mode)?;
+                Ok(GameState::from_raw_with_mode(value, mode, cutscene_mode))
+            }
+            _ => Ok(GameState::from_raw(value)),
+        }
+    }
+
+    // Why saving right now isn't safe, if it isn't.
+    pub fn save_blocker(&self) -> Option<&'static str> {
+        match *self {
+            GameState::InLevel | GameState::Paused => None,
+            GameState::Inactive => Some("not in a stage"),
+            GameState::Loading => Some("the stage is still loading"),
+            GameState::Cutscene => Some("a cutscene is playing"),
+            GameState::Dying => Some("the character is dying"),
+            GameState::Restarting => Some("the stage is restarting"),
+            GameState::Exiting => Some("leaving the stage"),
+            GameState::Unknown(_) => Some("unknown game state"),
+        }
+    }
+
+    // Why loading right now isn't safe, if it isn't. A load while paused
+    // can wait until the game is unpaused (see load_waits).
+    pub fn load_blocker(&self) -> Option<&'static str> {
+        match *self {
+            GameState::InLevel => None,
+            GameState::Paused => Some("the game is paused"),
+            other => other.save_blocker(),
+        }
+    }
+
+    // Whether a blocked load should happen once the game's back in the
+    // stage, instead of being thrown away.
+    pub fn load_waits(&self) -> bool {
+        *self == GameState::Paused
+    }
+
+    // Whether the stage is still around, so loads that are in progress or
+    // waiting can carry on.
+    pub fn in_stage(&self) -> bool {
+        matches!(*self, GameState::InLevel | GameState::Paused)
+    }
+}
+
+// How many frames the stage timer has to stand still in the stage before
+// StageClock calls it a cutscene. While playing it moves every frame, and
+// a load only holds it still for a frame or two.
+pub const TIMER_STOPPED_FRAMES: u32 = 30;
+
+// Spots in-stage cutscenes from the stage timer at `time`, which stops for
+// them while the frame counter keeps going. Needs to see every pass, so
+// keep one around and check() each game state read.
+pub struct StageClock {
+    time: [u8;0x3],
+    // The frame the timer was last seen to change on, if it's been watched
+    // since the game was last out of the stage.
+    changed_on: Option<u32>,
+}
+
+impl StageClock {
+    pub fn new() -> StageClock {
+        StageClock {
+            time: [0;0x3],
+            changed_on: None,
+        }
+    }
+
+    // `state`, or Cutscene if it's InLevel but the timer has stood still
+    // for TIMER_STOPPED_FRAMES frames.
+    pub fn check(&mut self, handle: &dyn GameMemory, addresses: &Addresses, state: GameState) -> Result<GameState, Error> {
+        if state != GameState::InLevel {
+            // The timer stops while paused too, so start over afterwards.
+            self.changed_on = None;
+            return Ok(state);
+        }
+        let mut time = [0;0x3];
+        handle.read_exact(addresses.time, &mut time)?;
+        let frame = handle.read_u32(addresses.frame_counter)?;
+        match self.changed_on {
+            Some(changed_on) if time == self.time => {
+                if frame.wrapping_sub(changed_on) >= TIMER_STOPPED_FRAMES {
+                    return Ok(GameState::Cutscene);
+                }
+            }
+            _ => {
+                self.time = time;
+                self.changed_on = Some(frame);
+            }
+        }
+        Ok(state)
+    }
+}
@@ -1,13 +1,244 @@
+#![allow(clippy::new_without_default, clippy::enum_variant_names)]
+
+// Lets #[derive(SaveStateable)] refer to ::onvars_tool from inside this crate.
+extern crate self as onvars_tool;
+
+pub mod addresses;
+pub mod batch;
+pub mod bindings;
+pub mod build;
+#[cfg(target_os = "linux")]
+pub mod dry_run;
+pub mod error;
+pub mod fake_memory;
+pub mod game_state;
+pub mod load_scheduler;
+pub mod memory_image;
+#[cfg(any(windows, target_os = "linux"))]
 pub mod process_reader;
 pub mod sa2_structures;
 pub mod sa2_units;
+pub mod session;
+pub mod state_file;
+pub mod trace;
+
+pub use onvars_derive::SaveStateable;
+
+use batch::MemoryRange;
+use error::{AccessKind, Error};
+use load_scheduler::LoadSchedule;
+use state_file::StateReader;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    // Converts bytes between this order and little-endian. Swapping is its
+    // own inverse, so this works in either direction.
+    pub fn reorder(self, bytes: &mut [u8]) {
+        if self == ByteOrder::Big {
+            bytes.reverse();
+        }
+    }
+}
+
+// Represents something that lets us read and write the game's memory.
+// Backends only need to provide read_data and write_data, which report how
+// many bytes were actually transferred. Everything else goes through
+// read_exact/write_all, which turn short transfers into errors.
+// Backends for games that aren't little-endian with 32-bit pointers (the
+// PC version) say so with byte_order and pointer_size, and every multi-byte
+// value read through here is converted to match.
+pub trait GameMemory {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error>;
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error>;
+
+    fn byte_order(&self) -> ByteOrder {
+        ByteOrder::Little
+    }
+
+    // In bytes, 4 or 8.
+    fn pointer_size(&self) -> usize {
+        4
+    }
+
+    fn read_exact(&self, address: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let bytes_read = self.read_data(address, buf)?;
+        if bytes_read != buf.len() {
+            return Err(Error::PartialAccess {
+                kind: AccessKind::Read,
+                address,
+                requested: buf.len(),
+                transferred: bytes_read,
+            });
+        }
+        Ok(())
+    }
+
+    fn write_all(&self, address: u64, buf: &[u8]) -> Result<(), Error> {
+        let bytes_written = self.write_data(address, buf)?;
+        if bytes_written != buf.len() {
+            return Err(Error::PartialAccess {
+                kind: AccessKind::Write,
+                address,
+                requested: buf.len(),
+                transferred: bytes_written,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_u8(&self, address: u64) -> Result<u8, Error> {
+        let mut buf = [0; 1];
+        self.read_exact(address, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_u16(&self, address: u64) -> Result<u16, Error> {
+        let mut buf = [0; 2];
+        self.read_exact(address, &mut buf)?;
+        self.byte_order().reorder(&mut buf);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn write_u16(&self, address: u64, value: u16) -> Result<(), Error> {
+        let mut buf = value.to_le_bytes();
+        self.byte_order().reorder(&mut buf);
+        self.write_all(address, &buf)
+    }
+
+    fn read_i32(&self, address: u64) -> Result<i32, Error> {
+        let mut buf = [0; 4];
+        self.read_exact(address, &mut buf)?;
+        self.byte_order().reorder(&mut buf);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn read_u32(&self, address: u64) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        self.read_exact(address, &mut buf)?;
+        self.byte_order().reorder(&mut buf);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn write_u32(&self, address: u64, value: u32) -> Result<(), Error> {
+        let mut buf = value.to_le_bytes();
+        self.byte_order().reorder(&mut buf);
+        self.write_all(address, &buf)
+    }
+
+    // The pointer stored at `address`, which can be null.
+    fn read_ptr(&self, address: u64) -> Result<u64, Error> {
+        let mut buf = [0; 8];
+        let size = self.pointer_size();
+        self.read_exact(address, &mut buf[..size])?;
+        self.byte_order().reorder(&mut buf[..size]);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+// So wrappers like build::ReadOnly can hold any backend, owned or
+// borrowed.
+impl<M: GameMemory + ?Sized> GameMemory for Box<M> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_data(address, buf)
+    }
 
-use process_reader::ProcessHandle;
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        (**self).write_data(address, buf)
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        (**self).byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        (**self).pointer_size()
+    }
+}
+
+impl<M: GameMemory + ?Sized> GameMemory for &M {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_data(address, buf)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        (**self).write_data(address, buf)
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        (**self).byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        (**self).pointer_size()
+    }
+}
 
 // Represents a structure that can be savestated.
+// write_state/read_state (de)serialize whatever save captured, so it can be
+// stored in a savestate file.
 pub trait SaveStateable {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str>;
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str>;
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error>;
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error>;
+    fn write_state(&self, out: &mut Vec<u8>);
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error>;
+
+    // Saves/loads a run of these, `stride` bytes apart. Types that can do
+    // the whole run in one transfer (like u8) override these.
+    fn save_slice(items: &mut [Self], handle: &dyn GameMemory, address: u64, stride: u64) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        for (idx, item) in items.iter_mut().enumerate() {
+            item.save(handle, address + idx as u64 * stride)?;
+        }
+        Ok(())
+    }
+
+    fn load_slice(items: &[Self], handle: &dyn GameMemory, address: u64, stride: u64) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        for (idx, item) in items.iter().enumerate() {
+            item.load(handle, address + idx as u64 * stride)?;
+        }
+        Ok(())
+    }
+}
+
+// Something that always covers the same number of bytes of game memory.
+// Arrays and tuples use this to work out where each element is. ALIGN is
+// what it's aligned to in the game's C structs.
+pub trait FixedSize {
+    const SIZE: u64;
+    const ALIGN: u64;
+}
+
+// `offset` rounded up to a multiple of `align`.
+pub const fn align_up(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+// Decimal, or hex with 0x in front.
+pub fn parse_number(text: &str) -> Option<u64> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u64::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// Reads the pointer stored at `address`. Null is an error.
+pub fn read_pointer(handle: &dyn GameMemory, address: u64) -> Result<u64, Error> {
+    let ptr_value = handle.read_ptr(address)?;
+    if ptr_value == 0 {
+        return Err(Error::NullPointer { address });
+    }
+    Ok(ptr_value)
 }
 
 // Nice type to do pointers for us.
@@ -23,53 +254,303 @@ impl<T> SaveStateable for Pointer<T>
 where
     T: SaveStateable,
 {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        let ptr_value = handle.read_u32(address)? as u64;
-        if ptr_value != 0 {
-            self.0.save(handle, ptr_value)
-        } else {
-            Err("reading null pointer")
-        }
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        self.0.save(handle, read_pointer(handle, address)?)
     }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        let ptr_value = handle.read_u32(address)? as u64;
-        if ptr_value != 0 {
-            self.0.load(handle, ptr_value)
-        } else {
-            Err("reading null pointer")
-        }
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        self.0.load(handle, read_pointer(handle, address)?)
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        self.0.write_state(out)
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        self.0.read_state(input)
     }
 }
 
-// impl for u8 for convenience
-// Probably should have more types, too.
+// A run of bytes whose length is only known at run time, like structs
+// whose size comes from the address database. Savestates store just the
+// bytes, so both sides need to agree on the length.
+pub struct Bytes(Vec<u8>);
+
+impl Bytes {
+    pub fn new(len: u64) -> Bytes {
+        Bytes(vec![0; len as usize])
+    }
+}
+
+impl SaveStateable for Bytes {
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        handle.read_exact(address, &mut self.0)
+    }
+
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        handle.write_all(address, &self.0)
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        input.read_bytes(&mut self.0)
+    }
+}
+
+// u8 gets its own impl so runs of bytes are one transfer instead of one
+// per byte.
 impl SaveStateable for u8 {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
         *self = handle.read_u8(address)?;
         Ok(())
     }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &[*self])?;
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        handle.write_all(address, &[*self])?;
+        Ok(())
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        *self = input.read_u8()?;
         Ok(())
     }
+
+    fn save_slice(items: &mut [u8], handle: &dyn GameMemory, address: u64, stride: u64) -> Result<(), Error> {
+        if stride != 1 {
+            for (idx, item) in items.iter_mut().enumerate() {
+                item.save(handle, address + idx as u64 * stride)?;
+            }
+            return Ok(());
+        }
+        handle.read_exact(address, items)
+    }
+
+    fn load_slice(items: &[u8], handle: &dyn GameMemory, address: u64, stride: u64) -> Result<(), Error> {
+        if stride != 1 {
+            for (idx, item) in items.iter().enumerate() {
+                item.load(handle, address + idx as u64 * stride)?;
+            }
+            return Ok(());
+        }
+        handle.write_all(address, items)
+    }
+}
+
+impl FixedSize for u8 {
+    const SIZE: u64 = 1;
+    const ALIGN: u64 = 1;
+}
+
+// Everything else is in the game's byte order in memory, and
+// little-endian in savestates.
+macro_rules! impl_primitive {
+    ($($ty:ty),*) => {$(
+        impl SaveStateable for $ty {
+            fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+                let mut buf = [0; std::mem::size_of::<$ty>()];
+                handle.read_exact(address, &mut buf)?;
+                handle.byte_order().reorder(&mut buf);
+                *self = <$ty>::from_le_bytes(buf);
+                Ok(())
+            }
+
+            fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+                let mut buf = self.to_le_bytes();
+                handle.byte_order().reorder(&mut buf);
+                handle.write_all(address, &buf)
+            }
+
+            fn write_state(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+                let mut buf = [0; std::mem::size_of::<$ty>()];
+                input.read_bytes(&mut buf)?;
+                *self = <$ty>::from_le_bytes(buf);
+                Ok(())
+            }
+        }
+
+        // Every primitive is aligned to its size.
+        impl FixedSize for $ty {
+            const SIZE: u64 = std::mem::size_of::<$ty>() as u64;
+            const ALIGN: u64 = std::mem::size_of::<$ty>() as u64;
+        }
+    )*};
 }
 
-impl SaveStateable for u32 {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        *self = handle.read_u32(address)?;
+impl_primitive!(i8, i16, u16, i32, u32, u64, f32, f64);
+
+// Arrays are packed, one element every T::SIZE bytes. Use Strided when
+// there's other data between the elements.
+impl<T, const N: usize> SaveStateable for [T; N]
+where
+    T: SaveStateable + FixedSize,
+{
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        T::save_slice(self, handle, address, T::SIZE)
+    }
+
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        T::load_slice(self, handle, address, T::SIZE)
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        for item in self.iter() {
+            item.write_state(out);
+        }
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        for item in self.iter_mut() {
+            item.read_state(input)?;
+        }
         Ok(())
     }
+}
+
+impl<T: FixedSize, const N: usize> FixedSize for [T; N] {
+    const SIZE: u64 = T::SIZE * N as u64;
+    const ALIGN: u64 = T::ALIGN;
+}
+
+// An array with an element every STRIDE bytes.
+// Only the elements themselves are saved, not what's between them.
+pub struct Strided<T, const N: usize, const STRIDE: u64>(pub [T; N]);
+
+impl<T, const N: usize, const STRIDE: u64> Strided<T, N, STRIDE> {
+    pub fn new(items: [T; N]) -> Strided<T, N, STRIDE> {
+        Strided(items)
+    }
+}
+
+impl<T, const N: usize, const STRIDE: u64> SaveStateable for Strided<T, N, STRIDE>
+where
+    T: SaveStateable,
+{
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        T::save_slice(&mut self.0, handle, address, STRIDE)
+    }
+
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        T::load_slice(&self.0, handle, address, STRIDE)
+    }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_u32(address, *self)?;
+    fn write_state(&self, out: &mut Vec<u8>) {
+        for item in self.0.iter() {
+            item.write_state(out);
+        }
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        for item in self.0.iter_mut() {
+            item.read_state(input)?;
+        }
         Ok(())
     }
 }
 
+impl<T: FixedSize, const N: usize, const STRIDE: u64> FixedSize for Strided<T, N, STRIDE> {
+    const SIZE: u64 = STRIDE * N as u64;
+    const ALIGN: u64 = T::ALIGN;
+}
+
+// Tuples are laid out like C structs: each field is aligned to its ALIGN,
+// and the whole tuple is padded to a multiple of its largest field's
+// alignment. So (u8, u16, u32) has a byte of padding after the u8 and is 8
+// bytes. Only the fields are saved, not the padding.
+macro_rules! impl_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name),+> SaveStateable for ($($name,)+)
+        where
+            $($name: SaveStateable + FixedSize),+
+        {
+            #[allow(unused_assignments)]
+            fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+                let mut offset = 0;
+                $(
+                    offset = align_up(offset, $name::ALIGN);
+                    self.$idx.save(handle, address + offset)?;
+                    offset += $name::SIZE;
+                )+
+                Ok(())
+            }
+
+            #[allow(unused_assignments)]
+            fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+                let mut offset = 0;
+                $(
+                    offset = align_up(offset, $name::ALIGN);
+                    self.$idx.load(handle, address + offset)?;
+                    offset += $name::SIZE;
+                )+
+                Ok(())
+            }
+
+            fn write_state(&self, out: &mut Vec<u8>) {
+                $(self.$idx.write_state(out);)+
+            }
+
+            fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+                $(self.$idx.read_state(input)?;)+
+                Ok(())
+            }
+        }
+
+        impl<$($name: FixedSize),+> FixedSize for ($($name,)+) {
+            const SIZE: u64 = {
+                let mut offset = 0;
+                $(offset = align_up(offset, $name::ALIGN) + $name::SIZE;)+
+                align_up(offset, Self::ALIGN)
+            };
+            const ALIGN: u64 = {
+                let mut align = 1;
+                $(if $name::ALIGN > align {
+                    align = $name::ALIGN;
+                })+
+                align
+            };
+        }
+    };
+}
+
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+
 // Represents a whole unit of stuff to save.
+// The name identifies the unit's chunk in savestate files, so it shouldn't
+// change once released.
 pub trait SaveStateUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str>;
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str>;
+    fn name(&self) -> &'static str;
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error>;
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error>;
+    fn write_state(&self, out: &mut Vec<u8>);
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error>;
+
+    // Whether load needs repeating on later frames. See load_scheduler.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::Immediate
+    }
+
+    // The memory at fixed addresses that save and load use, so it can all
+    // be read up front. Anything behind a pointer is left out. See batch.rs.
+    fn regions(&self) -> Vec<MemoryRange> {
+        Vec::new()
+    }
+
+    // Whether savestate files can be without this unit's chunk. Files made
+    // before the unit was added, or without it turned on, don't have one,
+    // and loading them leaves what the unit covers alone.
+    fn optional(&self) -> bool {
+        false
+    }
 }
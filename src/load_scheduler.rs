@@ -0,0 +1,163 @@
+// Applying a savestate over several frames.
+//
+// Some state gets recalculated by the game on the frame after a load
+// (collision, mostly), so a unit might need writing more than once. Each
+// SaveStateUnit says when through load_schedule(), and LoadScheduler keeps
+// track of which units still need loading as the game's frame counter
+// advances. Every unit is loaded once straight away regardless.
+//
+// Each round of loads goes through a Batch, so its writes are made
+// together at the end of the round. Nothing's prefetched: loads mostly
+// write, and only read the pointers they follow.
+
+use crate::{GameMemory, SaveStateUnit};
+use crate::batch::Batch;
+use crate::error::Error;
+
+// read_u32(address) & mask == value
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct MemoryCondition {
+    pub address: u64,
+    pub mask: u32,
+    pub value: u32,
+}
+
+impl MemoryCondition {
+    pub fn new(address: u64, mask: u32, value: u32) -> MemoryCondition {
+        MemoryCondition {
+            address,
+            mask,
+            value,
+        }
+    }
+
+    pub fn holds(&self, handle: &dyn GameMemory) -> Result<bool, Error> {
+        Ok(handle.read_u32(self.address)? & self.mask == self.value)
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum LoadSchedule {
+    // Just the once.
+    Immediate,
+    // Again once the game is N frames past the frame it was loaded on.
+    AfterFrames(u32),
+    // Again on every new frame, until it's been loaded on N frames
+    // (counting the first).
+    EveryFrame(u32),
+    // Again on every new frame until the condition holds. Gives up after
+    // `max_frames` frames.
+    Until {
+        condition: MemoryCondition,
+        max_frames: u32,
+    },
+}
+
+struct PendingLoad {
+    unit: usize,
+    schedule: LoadSchedule,
+    last_frame: u32,
+}
+
+pub struct LoadScheduler {
+    pending: Vec<PendingLoad>,
+    start_frame: u32,
+}
+
+impl LoadScheduler {
+    pub fn new() -> LoadScheduler {
+        LoadScheduler {
+            pending: Vec::new(),
+            start_frame: 0,
+        }
+    }
+
+    // Nothing left to load.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+
+    // Loads every unit now, and remembers which ones need loading again.
+    // Anything left over from an earlier load is dropped. `units` has to be
+    // passed to update() in the same order.
+    pub fn start(&mut self, handle: &dyn GameMemory, units: &[&dyn SaveStateUnit], frame: u32) -> Vec<Error> {
+        self.pending.clear();
+        self.start_frame = frame;
+
+        let batch = Batch::new(handle);
+        let mut errors = Vec::new();
+        for (idx, unit) in units.iter().enumerate() {
+            if let Err(err) = unit.load(&batch) {
+                errors.push(err);
+            }
+            let schedule = unit.load_schedule();
+            if schedule != LoadSchedule::Immediate {
+                self.pending.push(PendingLoad {
+                    unit: idx,
+                    schedule,
+                    last_frame: frame,
+                });
+            }
+        }
+        if let Err(err) = batch.flush() {
+            errors.push(err);
+        }
+        errors
+    }
+
+    // Call once per poll with the current frame counter.
+    pub fn update(&mut self, handle: &dyn GameMemory, units: &[&dyn SaveStateUnit], frame: u32) -> Vec<Error> {
+        let elapsed = frame.wrapping_sub(self.start_frame);
+        let batch = Batch::new(handle);
+        let mut errors = Vec::new();
+        self.pending.retain_mut(|pending| {
+            let unit = match units.get(pending.unit) {
+                Some(unit) => unit,
+                None => return false,
+            };
+            if pending.last_frame == frame {
+                return true;
+            }
+            pending.last_frame = frame;
+
+            let (load, keep) = match pending.schedule {
+                LoadSchedule::Immediate => (false, false),
+                LoadSchedule::AfterFrames(frames) => {
+                    let due = elapsed >= frames;
+                    (due, !due)
+                }
+                LoadSchedule::EveryFrame(frames) => {
+                    let due = elapsed < frames;
+                    (due, due)
+                }
+                LoadSchedule::Until { condition, max_frames } => {
+                    match condition.holds(&batch) {
+                        Ok(true) => (false, false),
+                        Ok(false) => {
+                            let due = elapsed <= max_frames;
+                            (due, due)
+                        }
+                        Err(err) => {
+                            errors.push(err);
+                            (false, false)
+                        }
+                    }
+                }
+            };
+            if load {
+                if let Err(err) = unit.load(&batch) {
+                    errors.push(err);
+                }
+            }
+            keep
+        });
+        if let Err(err) = batch.flush() {
+            errors.push(err);
+        }
+        errors
+    }
+}
@@ -1,20 +1,100 @@
+use std::convert::TryFrom;
+use std::env;
+#[cfg(any(windows, target_os = "linux"))]
 use std::io::{self, Write};
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
-use onvars_tool::SaveStateUnit;
-use onvars_tool::sa2_units::{CharacterUnit, CameraUnit, TimeUnit, GravityUnit};
+use onvars_tool::{parse_number, GameMemory};
+use onvars_tool::addresses::{AddressDatabase, Addresses, DEFAULT_SECTION};
+use onvars_tool::bindings::{Action, Bindings};
+use onvars_tool::build::{BuildInfo, ReadOnly};
+use onvars_tool::dry_run::{self, DryRun};
+use onvars_tool::error::Error;
+use onvars_tool::game_state::GameState;
+use onvars_tool::memory_image::MemoryImage;
+#[cfg(any(windows, target_os = "linux"))]
 use onvars_tool::process_reader::ProcessHandle;
+use onvars_tool::session::{Session, Slot, UnitOptions};
+use onvars_tool::state_file::StateHeader;
+use onvars_tool::trace::{Recorder, Replay, Trace};
 
-fn main() {
-    println!("OnVar's Tool (version {})", env!("CARGO_PKG_VERSION"));
+const BINDINGS_FILE: &str = "bindings.cfg";
+
+const ADDRESSES_FILE: &str = "addresses.cfg";
+
+const IMAGE_STATE_FILE: &str = "savestate_image.ovst";
+
+// Command line options.
+struct Options {
+    units: UnitOptions,
+    // Write to the game even if it's not a build we know.
+    trust_build: bool,
+    // A minidump or region manifest to save a state from, instead of
+    // attaching to the game.
+    image: Option<PathBuf>,
+    // Write every access to the game to this trace file.
+    record: Option<PathBuf>,
+    // Play back a trace file instead of attaching to the game.
+    replay: Option<PathBuf>,
+    // Print what loading would write instead of writing it.
+    dry_run: bool,
+}
+
+impl Options {
+    fn from_args() -> Options {
+        let mut options = Options {
+            units: UnitOptions::new(),
+            trust_build: false,
+            image: None,
+            record: None,
+            replay: None,
+            dry_run: false,
+        };
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--all-players" => options.units.all_players = true,
+                "--keep-rings" => options.units.counters.rings = false,
+                "--keep-lives" => options.units.counters.lives = false,
+                "--keep-score" => options.units.counters.score = false,
+                "--trust-build" => options.trust_build = true,
+                "--dry-run" => options.dry_run = true,
+                "--restore-collision" => options.units.restore_collision = true,
+                "--restore-rng" => options.units.restore_rng = true,
+                _ if arg.starts_with("--image=") => {
+                    options.image = Some(PathBuf::from(&arg["--image=".len()..]));
+                }
+                _ if arg.starts_with("--record=") => {
+                    options.record = Some(PathBuf::from(&arg["--record=".len()..]));
+                }
+                _ if arg.starts_with("--replay=") => {
+                    options.replay = Some(PathBuf::from(&arg["--replay=".len()..]));
+                }
+                _ if arg.starts_with("--lock-rng=") => {
+                    match parse_number(&arg["--lock-rng=".len()..]).and_then(|seed| u32::try_from(seed).ok()) {
+                        Some(seed) => {
+                            options.units.restore_rng = true;
+                            options.units.rng_lock = Some(seed);
+                        }
+                        None => println!("Ignoring \"{}\": the seed should be a number.", arg),
+                    }
+                }
+                _ => println!("Ignoring unknown option \"{}\".", arg),
+            }
+        }
+        options
+    }
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+fn hook_game() -> Box<dyn GameMemory> {
     let mut process_string = "sonic2app.exe".to_string();
-    let handle;
+    let process;
     'process_hook_loop: loop {
         match ProcessHandle::from_name_filter(|n| n.to_lowercase() == process_string.to_lowercase()).unwrap() {
             Some(h) => {
-                handle = h;
+                process = h;
                 break 'process_hook_loop;
             }
             None => {
@@ -33,83 +113,291 @@ fn main() {
 
     println!();
     println!("Successfully hooked into \"{}\".", process_string);
+    Box::new(process)
+}
+
+// Memory images and traces still work here, just not the game itself.
+#[cfg(not(any(windows, target_os = "linux")))]
+fn hook_game() -> Box<dyn GameMemory> {
+    println!("Hooking into the game is only supported on Windows and Linux.");
+    std::process::exit(1)
+}
+
+// What we found out about the game's memory.
+struct Game {
+    build: Option<BuildInfo>,
+    // The build's section in the address database, if it's a known build.
+    build_name: Option<String>,
+    addresses: Addresses,
+}
+
+// Works out the build and its addresses, using the user's addresses file on
+// top of the built-in ones. None if some addresses are missing.
+fn identify_game(memory: &dyn GameMemory) -> Option<Game> {
+    let mut database = AddressDatabase::default();
+    if Path::new(ADDRESSES_FILE).exists() {
+        match AddressDatabase::read_file(Path::new(ADDRESSES_FILE)) {
+            Ok(user_database) => {
+                println!("Using addresses from \"{}\".", ADDRESSES_FILE);
+                database.merge(user_database);
+            }
+            Err(err) => {
+                println!("Error in \"{}\": {}", ADDRESSES_FILE, err);
+                println!("Using built-in addresses.");
+            }
+        }
+    }
+
+    // Only write to builds we know the addresses are right for.
+    let build = match BuildInfo::detect(memory) {
+        Ok(build) => Some(build),
+        Err(err) => {
+            println!("Error: {}", err);
+            None
+        }
+    };
+    let build_name = match build.and_then(|build| database.build_name(build.header_crc)) {
+        Some(name) => {
+            println!("Game build: {}.", name);
+            Some(name.to_string())
+        }
+        None => {
+            if let Some(build) = build {
+                println!("Unrecognized game build (timestamp {:#010x}, header CRC {:#010x}).", build.timestamp, build.header_crc);
+            }
+            None
+        }
+    };
+    match database.addresses(build_name.as_deref().unwrap_or(DEFAULT_SECTION)) {
+        Ok(addresses) => Some(Game {
+            build,
+            build_name,
+            addresses,
+        }),
+        Err(err) => {
+            println!("Error: {}", err);
+            println!("Add the missing addresses to \"{}\".", ADDRESSES_FILE);
+            None
+        }
+    }
+}
+
+// Saves a state from a memory image, to see what the game looked like when
+// it was taken. The state can be loaded into the game like any slot's.
+fn inspect_image(path: &Path, options: &Options) {
+    let image = match MemoryImage::open(path) {
+        Ok(image) => image,
+        Err(err) => {
+            println!("Error in \"{}\": {}", path.display(), err);
+            return;
+        }
+    };
+    println!("Opened \"{}\" ({} memory regions).", path.display(), image.region_count());
+    let game = match identify_game(&image) {
+        Some(game) => game,
+        None => return,
+    };
+    match GameState::read(&image, &game.addresses) {
+        Ok(game_state) => println!("Game state: {:?}.", game_state),
+        Err(err) => println!("Error: {}", err),
+    }
+    let level = match image.read_u32(game.addresses.level) {
+        Ok(level) => level,
+        Err(err) => {
+            println!("Error: {}", err);
+            return;
+        }
+    };
+    println!("Level: {}.", level);
+
+    let mut slot = Slot::new(&options.units, &game.addresses);
+    for err in slot.save(&image, level) {
+        println!("Error: {}", err);
+    }
+    if !slot.valid {
+        println!("Could not save every unit. Not writing a savestate.");
+        return;
+    }
+    let build_id = game.build.map(|build| build.state_id()).unwrap_or(0);
+    let result = StateHeader::read(&image, &game.addresses, build_id)
+        .and_then(|header| slot.write_file(Path::new(IMAGE_STATE_FILE), &header));
+    match result {
+        Ok(()) => println!("Saved state to \"{}\".", IMAGE_STATE_FILE),
+        Err(err) => println!("Error: {}", err),
+    }
+}
+
+fn main() {
+    println!("OnVar's Tool (version {})", env!("CARGO_PKG_VERSION"));
+    let options = Options::from_args();
+    if let Some(ref path) = options.image {
+        inspect_image(path, &options);
+        return;
+    }
+    // Replays run main's logic against the trace until it runs out, then
+    // exit with whether it matched. The replay lives as long as the tool.
+    let replay: Option<&'static Replay> = match options.replay {
+        Some(ref path) => match Trace::read_file(path) {
+            Ok(trace) => Some(Box::leak(Box::new(Replay::new(trace)))),
+            Err(err) => {
+                println!("Error in \"{}\": {}", path.display(), err);
+                return;
+            }
+        },
+        None => None,
+    };
+    let process: Box<dyn GameMemory> = match replay {
+        Some(replay) => Box::new(replay),
+        None => hook_game(),
+    };
+    let process: Box<dyn GameMemory> = match options.record {
+        Some(ref path) => match Recorder::create(process, path) {
+            Ok(recorder) => {
+                println!("Recording a trace to \"{}\".", path.display());
+                Box::new(recorder)
+            }
+            Err(err) => {
+                println!("Error: {}", err);
+                return;
+            }
+        },
+        None => process,
+    };
+
+    let game = match identify_game(&*process) {
+        Some(game) => game,
+        None => return,
+    };
+    let build = game.build;
+    let build_name = game.build_name;
+    let addresses = game.addresses;
+    let writable = build_name.is_some() || options.trust_build;
+    let handle: Box<dyn GameMemory> = if writable {
+        process
+    } else {
+        println!("Running read-only: states can be saved but not loaded.");
+        println!("Start with --trust-build to load states anyway, at your own risk.");
+        Box::new(ReadOnly::new(process))
+    };
     println!();
-    println!("Press D-pad Left to save a state.");
-    println!("Press D-pad Right to load a state.");
-
-    let mut units: Vec<Rc<dyn SaveStateUnit>> = vec![
-        Rc::new(CharacterUnit::new()),
-        Rc::new(CameraUnit::new()),
-        Rc::new(TimeUnit::new()),
-        Rc::new(GravityUnit::new()),
-    ];
-
-    let mut prev_buttons = 0;
-    let mut save_level = 0;
-    let mut frame_opt = None;
-    let mut save_valid = false;
-    let mut prev_game_state = 0;
-    loop {
-        let mut score = handle.read_u32(0x0174B050).unwrap();
-        score = score - (score % 10) + 1;
-        handle.write_u32(0x0174B050, score).unwrap();
-        let buttons = handle.read_u32(0x01A52C4C).unwrap();
-        let buttons_pressed = !prev_buttons & buttons;
-        prev_buttons = buttons;
-
-        let level = handle.read_u32(0x1934B70).unwrap();
-
-        let game_state = handle.read_u32(0x1934BE0).unwrap();
-        if prev_game_state != 0 && game_state == 0 {
-            save_valid = false;
-            println!("Exited level. Invalidating savestate.")
-        }
-        prev_game_state = game_state;
-
-        if buttons_pressed & 0x1 != 0 {
-            if game_state != 0 {
-                save_level = level;
-                save_valid = true;
-                for unit in units.iter_mut() {
-                    match Rc::get_mut(unit).unwrap().save(&handle) {
-                        Ok(()) => {}
-                        Err(string) => println!("Error: {}", string),
-                    }
-                }
-                println!("Saving state");
-            } else {
-                println!("Not in level. Cannot save state.")
+    let bindings = if Path::new(BINDINGS_FILE).exists() {
+        match Bindings::read_file(Path::new(BINDINGS_FILE)) {
+            Ok(bindings) => {
+                println!("Using button bindings from \"{}\".", BINDINGS_FILE);
+                bindings
+            }
+            Err(err) => {
+                println!("Error in \"{}\": {}", BINDINGS_FILE, err);
+                println!("Using default button bindings.");
+                Bindings::default()
             }
         }
+    } else {
+        Bindings::default()
+    };
+    println!();
+    for &(action, description) in [
+        (Action::Save, "save a state"),
+        (Action::Load, "load a state"),
+        (Action::NextSlot, "select the next slot"),
+        (Action::PrevSlot, "select the previous slot"),
+    ].iter() {
+        for binding in bindings.bindings_for(action) {
+            println!("Press {} to {}.", binding.describe(), description);
+        }
+    }
 
-        if buttons_pressed & 0x2 != 0 {
-            if !save_valid {
-                println!("Error: savestate not valid")
-            } else if level != save_level {
-                println!("Error: not the same stage as savestate");
-            } else {
-                println!("Loading state");
-                frame_opt = Some(handle.read_u32(0x0174b03c).unwrap());
-                for unit in units.iter() {
-                    match unit.load(&handle) {
-                        Ok(()) => {}
-                        Err(string) => println!("Error: {}", string),
-                    }
+    let units = options.units;
+    if units.all_players {
+        println!("Saving all players (2P mode).");
+    }
+    let counters = units.counters;
+    for &(restored, name) in [(counters.rings, "rings"), (counters.lives, "lives"), (counters.score, "score")].iter() {
+        if !restored {
+            println!("Loading keeps your current {}.", name);
+        }
+    }
+    if let Some(seed) = units.rng_lock {
+        println!("RNG locked to seed {:#x} (experimental).", seed);
+    } else if units.restore_rng {
+        println!("Restoring the RNG (experimental).");
+    }
+    if units.restore_collision {
+        println!("Restoring level collision (experimental).");
+    }
+
+    let build_id = build.map(|build| build.state_id()).unwrap_or(0);
+    let mut session = Session::new(&units, &addresses, bindings, build_id);
+    session.set_access(writable, writable || options.dry_run);
+    // Replays keep their slots in memory, so they don't depend on the
+    // savestate files or overwrite them. Recordings don't pick up the
+    // files either, so they replay the same way.
+    if replay.is_none() {
+        // The directory the tool was started from.
+        session.set_slot_dir(Path::new(""));
+        if options.record.is_none() {
+            session.read_slot_files();
+        }
+    }
+    for message in session.take_messages() {
+        println!("{}", message);
+    }
+    println!("Slot {} selected.", session.active_slot() + 1);
+
+    if options.dry_run {
+        println!("Dry run: loading only prints what it would write.");
+    }
+    // Loads go through this, and only write for real without --dry-run.
+    let dry_run = DryRun::new(&*handle);
+    let load_handle: &dyn GameMemory = if options.dry_run {
+        &dry_run
+    } else {
+        &*handle
+    };
+
+    let mut last_error = None;
+    let mut poll = || -> Result<(), Error> {
+        let result = session.poll(&*handle, load_handle);
+        for message in session.take_messages() {
+            println!("{}", message);
+        }
+        let writes = dry_run.new_writes();
+        if !writes.is_empty() {
+            print!("{}", dry_run::diff(&writes));
+        }
+        // Later passes of a load read back what the first one wrote, so
+        // the writes stay visible until it's done.
+        if !session.is_loading() {
+            dry_run.clear();
+        }
+        result
+    };
+
+    loop {
+        // Reads can fail if the game closes or unloads a level mid-frame.
+        // Report it (once) and keep going instead of taking the tool down.
+        match poll() {
+            Ok(()) => last_error = None,
+            Err(err) => {
+                if last_error.as_ref() != Some(&err) {
+                    println!("Error: {}", err);
                 }
+                last_error = Some(err);
             }
         }
 
-        // second-frame savestate load for collision stuff
-        if let Some(frame) = frame_opt {
-            if frame != handle.read_u32(0x0174b03c).unwrap() {
-                for unit in units.iter() {
-                    match unit.load(&handle) {
-                        Ok(()) => {}
-                        Err(string) => println!("Error: {}", string),
+        if let Some(replay) = replay {
+            if replay.is_over() {
+                match replay.finish() {
+                    Ok(()) => println!("Replay matched the trace."),
+                    Err(err) => {
+                        println!("Replay failed: {}", err);
+                        std::process::exit(1);
                     }
                 }
-                frame_opt = None;
+                return;
             }
+            continue;
         }
 
         thread::sleep(Duration::from_millis(10))
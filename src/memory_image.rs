@@ -0,0 +1,215 @@
+// A snapshot of the game's memory, for looking at what happened after the
+// fact (say, a load that crashed the game) on any machine.
+//
+// Snapshots come from either a Windows minidump of sonic2app.exe, or raw
+// dumps of single regions listed in a manifest:
+//
+//   # address   file
+//   0x00400000  sonic2app.bin
+//   0x01900000  globals.bin
+//
+// Each manifest line is the address a region starts at (decimal, or hex
+// starting with 0x) and the file with its bytes, relative to the
+// manifest. Regions can't overlap.
+//
+// Snapshots can be read like the game, but never written to.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::{parse_number, GameMemory};
+use crate::error::{AccessKind, Error};
+
+const MINIDUMP_SIGNATURE: &[u8] = b"MDMP";
+// Stream types from minidumpapiset.h. Smaller dumps have a memory list,
+// full memory dumps have a 64-bit one.
+const MEMORY_LIST_STREAM: u32 = 5;
+const MEMORY64_LIST_STREAM: u32 = 9;
+
+fn truncated() -> Error {
+    Error::InvalidImage { reason: "minidump is truncated" }
+}
+
+fn slice_at(data: &[u8], offset: u64, len: u64) -> Result<&[u8], Error> {
+    let end = offset.checked_add(len).ok_or_else(truncated)?;
+    if end > data.len() as u64 {
+        return Err(truncated());
+    }
+    Ok(&data[offset as usize..end as usize])
+}
+
+fn u32_at(data: &[u8], offset: u64) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(slice_at(data, offset, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn u64_at(data: &[u8], offset: u64) -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(slice_at(data, offset, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[derive(Clone,Debug,Default)]
+pub struct MemoryImage {
+    // Region contents, by start address.
+    regions: BTreeMap<u64, Vec<u8>>,
+}
+
+impl MemoryImage {
+    pub fn new() -> MemoryImage {
+        MemoryImage {
+            regions: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_region(&mut self, address: u64, data: Vec<u8>) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = address.checked_add(data.len() as u64)
+            .ok_or(Error::InvalidImage { reason: "region runs past the end of memory" })?;
+        let after_previous = self.regions.range(..address).next_back()
+            .is_none_or(|(&start, previous)| start + previous.len() as u64 <= address);
+        let before_next = self.regions.range(address..).next()
+            .is_none_or(|(&start, _)| end <= start);
+        if !after_previous || !before_next {
+            return Err(Error::InvalidImage { reason: "overlapping regions" });
+        }
+        self.regions.insert(address, data);
+        Ok(())
+    }
+
+    pub fn region_count(&self) -> usize {
+        self.regions.len()
+    }
+
+    // The region containing `address`, and how far into it `address` is.
+    fn region_at(&self, address: u64) -> Option<(&[u8], usize)> {
+        let (&start, data) = self.regions.range(..=address).next_back()?;
+        let offset = address - start;
+        if offset < data.len() as u64 {
+            Some((data, offset as usize))
+        } else {
+            None
+        }
+    }
+
+    pub fn parse_minidump(data: &[u8]) -> Result<MemoryImage, Error> {
+        if !data.starts_with(MINIDUMP_SIGNATURE) {
+            return Err(Error::InvalidImage { reason: "not a minidump" });
+        }
+        let stream_count = u32_at(data, 8)? as u64;
+        let directory = u32_at(data, 12)? as u64;
+
+        let mut image = MemoryImage::new();
+        let mut found_memory = false;
+        for idx in 0..stream_count {
+            let entry = directory + idx * 12;
+            let stream_type = u32_at(data, entry)?;
+            let stream = u32_at(data, entry + 8)? as u64;
+            match stream_type {
+                MEMORY_LIST_STREAM => {
+                    let range_count = u32_at(data, stream)? as u64;
+                    for range in 0..range_count {
+                        let descriptor = stream + 4 + range * 16;
+                        let address = u64_at(data, descriptor)?;
+                        let len = u32_at(data, descriptor + 8)? as u64;
+                        let rva = u32_at(data, descriptor + 12)? as u64;
+                        image.add_region(address, slice_at(data, rva, len)?.to_vec())?;
+                    }
+                    found_memory = true;
+                }
+                MEMORY64_LIST_STREAM => {
+                    let range_count = u64_at(data, stream)?;
+                    // The ranges' contents follow each other from here.
+                    let mut rva = u64_at(data, stream + 8)?;
+                    for range in 0..range_count {
+                        let descriptor = stream + 16 + range * 16;
+                        let address = u64_at(data, descriptor)?;
+                        let len = u64_at(data, descriptor + 8)?;
+                        image.add_region(address, slice_at(data, rva, len)?.to_vec())?;
+                        rva += len;
+                    }
+                    found_memory = true;
+                }
+                _ => {}
+            }
+        }
+        if !found_memory {
+            return Err(Error::InvalidImage { reason: "minidump has no memory in it" });
+        }
+        Ok(image)
+    }
+
+    // `dir` is where region files are looked for.
+    pub fn parse_manifest(text: &str, dir: &Path) -> Result<MemoryImage, Error> {
+        let mut image = MemoryImage::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidConfig { line: idx + 1, reason };
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let address = parts.next().unwrap();
+            let file = parts.next().map(str::trim).ok_or_else(|| invalid("expected \"address file\""))?;
+            let address = parse_number(address).ok_or_else(|| invalid("address is not a number"))?;
+            let data = fs::read(dir.join(file))
+                .map_err(|e| Error::Io { operation: "reading memory region", code: e.raw_os_error() })?;
+            image.add_region(address, data).map_err(|err| match err {
+                Error::InvalidImage { reason } => invalid(reason),
+                other => other,
+            })?;
+        }
+        Ok(image)
+    }
+
+    // Opens a minidump or a manifest, whichever `path` is.
+    pub fn open(path: &Path) -> Result<MemoryImage, Error> {
+        let data = fs::read(path)
+            .map_err(|e| Error::Io { operation: "reading memory image", code: e.raw_os_error() })?;
+        if data.starts_with(MINIDUMP_SIGNATURE) {
+            return MemoryImage::parse_minidump(&data);
+        }
+        let text = String::from_utf8(data)
+            .map_err(|_| Error::InvalidImage { reason: "not a minidump or a manifest" })?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        MemoryImage::parse_manifest(&text, dir)
+    }
+}
+
+impl GameMemory for MemoryImage {
+    // Reads can run from one region into the next if they're back to
+    // back. Running into a gap is a partial read, like with the game.
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (data, offset) = match self.region_at(address + done as u64) {
+                Some(found) => found,
+                None => break,
+            };
+            let chunk = (data.len() - offset).min(buf.len() - done);
+            buf[done..done + chunk].copy_from_slice(&data[offset..offset + chunk]);
+            done += chunk;
+        }
+        if done == 0 && !buf.is_empty() {
+            return Err(Error::Access {
+                kind: AccessKind::Read,
+                address,
+                requested: buf.len(),
+                code: None,
+            });
+        }
+        Ok(done)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        Err(Error::ReadOnly {
+            address,
+            requested: buf.len(),
+        })
+    }
+}
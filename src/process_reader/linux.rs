@@ -0,0 +1,142 @@
+use std::fs;
+use std::io;
+use std::vec::IntoIter;
+
+use libc::{c_void, iovec, pid_t};
+
+use crate::GameMemory;
+use crate::error::{AccessKind, Error};
+
+// Handle to a process running under Linux, which for SA2 means a Wine/Proton
+// process. Wine maps the game's image at the same addresses as Windows does,
+// so all of the usual addresses work unchanged.
+#[derive(Clone,Copy,Debug)]
+pub struct ProcessHandle(pid_t);
+
+impl ProcessHandle {
+    pub fn open_process_read_info(id: ProcessId) -> Result<ProcessHandle, Error> {
+        // There's nothing to open. Just make sure the process exists.
+        if fs::metadata(format!("/proc/{}", id.0)).is_err() {
+            return Err(Error::Process { operation: "opening process", code: None });
+        }
+        Ok(ProcessHandle(id.0))
+    }
+
+    // Wine processes show up with the Windows path of the exe as argv[0]
+    // (e.g. "Z:\...\sonic2app.exe"), so we take the last path component of
+    // that. Falls back to comm for anything without a command line.
+    pub fn get_name(&self) -> Result<String, Error> {
+        let cmdline = fs::read(format!("/proc/{}/cmdline", self.0))
+            .map_err(|e| Error::Process { operation: "reading process cmdline", code: e.raw_os_error() })?;
+        let arg0 = cmdline.split(|&b| b == 0).next().unwrap_or(&[]);
+        if !arg0.is_empty() {
+            let arg0 = String::from_utf8_lossy(arg0);
+            let name = arg0.rsplit(['/', '\\']).next().unwrap_or("");
+            if !name.is_empty() {
+                return Ok(name.to_string());
+            }
+        }
+
+        let comm = fs::read_to_string(format!("/proc/{}/comm", self.0))
+            .map_err(|e| Error::Process { operation: "reading process comm", code: e.raw_os_error() })?;
+        Ok(comm.trim_end_matches('\n').to_string())
+    }
+
+    pub fn from_name_filter<F>(mut filter: F) -> Result<Option<ProcessHandle>, Error>
+        where F: FnMut(String) -> bool,
+    {
+        let mut processes = ProcessIterator::new()?
+            .filter_map(|pid| {
+                let handle = ProcessHandle::open_process_read_info(pid).ok()?;
+                let name = handle.get_name().ok()?;
+                if filter(name) {
+                    Some(handle)
+                }
+                else {
+                    None
+                }
+            });
+        Ok(processes.next())
+    }
+}
+
+impl GameMemory for ProcessHandle {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let local = iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+        let remote = iovec {
+            iov_base: address as usize as *mut c_void,
+            iov_len: buf.len(),
+        };
+        let result = unsafe { libc::process_vm_readv(self.0, &local, 1, &remote, 1, 0) };
+        if result < 0 {
+            return Err(Error::Access {
+                kind: AccessKind::Read,
+                address,
+                requested: buf.len(),
+                code: io::Error::last_os_error().raw_os_error(),
+            });
+        }
+        Ok(result as usize)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        let local = iovec {
+            iov_base: buf.as_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+        let remote = iovec {
+            iov_base: address as usize as *mut c_void,
+            iov_len: buf.len(),
+        };
+        let result = unsafe { libc::process_vm_writev(self.0, &local, 1, &remote, 1, 0) };
+        if result < 0 {
+            return Err(Error::Access {
+                kind: AccessKind::Write,
+                address,
+                requested: buf.len(),
+                code: io::Error::last_os_error().raw_os_error(),
+            });
+        }
+        Ok(result as usize)
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+pub struct ProcessId(pid_t);
+
+impl ProcessId {
+    pub fn from_raw(pid: u32) -> ProcessId {
+        ProcessId(pid as pid_t)
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct ProcessIterator {
+    iter: IntoIter<pid_t>,
+}
+
+impl ProcessIterator {
+    pub fn new() -> Result<ProcessIterator, Error> {
+        let entries = fs::read_dir("/proc").map_err(|e| Error::Process { operation: "reading /proc", code: e.raw_os_error() })?;
+        let mut pids: Vec<pid_t> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect();
+        pids.sort_unstable();
+
+        Ok(ProcessIterator {
+            iter: pids.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ProcessIterator {
+    type Item = ProcessId;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(ProcessId)
+    }
+}
+
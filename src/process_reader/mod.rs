@@ -0,0 +1,13 @@
+// Platform specific ways of getting at the game's memory.
+// Every backend exposes the same ProcessHandle/ProcessId/ProcessIterator API
+// so main.rs doesn't care which one it's talking to.
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::{ProcessHandle, ProcessId, ProcessIterator};
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub use self::linux::{ProcessHandle, ProcessId, ProcessIterator};
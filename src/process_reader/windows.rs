@@ -1,7 +1,11 @@
 use std::mem;
 use std::ffi::CStr;
+use std::io;
 use std::vec::IntoIter;
 
+use crate::GameMemory;
+use crate::error::{AccessKind, Error};
+
 use winapi::ctypes::c_void;
 use winapi::shared::minwindef::{HMODULE, MAX_PATH};
 use winapi::shared::ntdef::NULL;
@@ -16,43 +20,43 @@ const PROCESS_BUFFER_LEN: usize = 1024;
 pub struct ProcessHandle(HANDLE);
 
 impl ProcessHandle {
-    fn open_process(id: ProcessId, mode: u32) -> Result<ProcessHandle, &'static str> {
+    fn open_process(id: ProcessId, mode: u32) -> Result<ProcessHandle, Error> {
         let handle;
         unsafe {
             handle = processthreadsapi::OpenProcess(mode, false as i32, id.0);
             if handle == NULL {
-                return Err("could not open process");
+                return Err(Error::Process { operation: "OpenProcess", code: io::Error::last_os_error().raw_os_error() });
             }
         }
         Ok(ProcessHandle(handle))
     }
 
-    pub fn open_process_read_info(id: ProcessId) -> Result<ProcessHandle, &'static str> {
+    pub fn open_process_read_info(id: ProcessId) -> Result<ProcessHandle, Error> {
         Self::open_process(id, PROCESS_QUERY_INFORMATION | PROCESS_VM_READ | PROCESS_VM_WRITE)
     }
 
-    pub fn get_name(&self) -> Result<String, &'static str> {
+    pub fn get_name(&self) -> Result<String, Error> {
         let name;
         unsafe {
             let mut module = mem::uninitialized();
             let mut bytes_needed = mem::uninitialized();
             let result = psapi::EnumProcessModules(self.0, &mut module as *mut HMODULE, mem::size_of::<HMODULE>() as u32, &mut bytes_needed as *mut u32);
             if result == 0 {
-                return Err("error in EnumProcessModules");
+                return Err(Error::Process { operation: "EnumProcessModules", code: io::Error::last_os_error().raw_os_error() });
             }
             let mut name_buffer = [0i8; MAX_PATH];
             let bytes_in_str = psapi::GetModuleBaseNameA(self.0, module, &mut name_buffer[0] as *mut i8, MAX_PATH as u32);
             let name_buffer: [u8; MAX_PATH] = mem::transmute(name_buffer);
             name = CStr::from_bytes_with_nul(&name_buffer[.. bytes_in_str as usize + 1])
-                .map_err(|_| "error converting process name")?
+                .map_err(|_| Error::Process { operation: "converting process name", code: None })?
                 .to_str()
-                .map_err(|_| "error converting process name")?
+                .map_err(|_| Error::Process { operation: "converting process name", code: None })?
                 .to_string();
         }
         Ok(name)
     }
 
-    pub fn from_name_filter<F>(mut filter: F) -> Result<Option<ProcessHandle>, &'static str>
+    pub fn from_name_filter<F>(mut filter: F) -> Result<Option<ProcessHandle>, Error>
         where F: FnMut(String) -> bool,
     {
         let mut processes = ProcessIterator::new()?
@@ -68,104 +72,64 @@ impl ProcessHandle {
             });
         Ok(processes.next())
     }
+}
 
-    pub fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+impl GameMemory for ProcessHandle {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
         let mut bytes_read;
         unsafe {
             bytes_read = mem::uninitialized();
-            let address = mem::transmute(address);
+            let remote_address = mem::transmute(address);
             let buf_addr = buf.as_mut_ptr() as *mut c_void;
-            let result = memoryapi::ReadProcessMemory(self.0, address, buf_addr, buf.len(), &mut bytes_read as *mut usize);
+            let result = memoryapi::ReadProcessMemory(self.0, remote_address, buf_addr, buf.len(), &mut bytes_read as *mut usize);
             if result == 0 {
-                return Err("Error in ReadProcessMemory");
+                return Err(Error::Access {
+                    kind: AccessKind::Read,
+                    address,
+                    requested: buf.len(),
+                    code: io::Error::last_os_error().raw_os_error(),
+                });
             }
         }
         Ok(bytes_read)
     }
 
-    pub fn read_u8(&self, address: u64) -> Result<u8, &'static str> {
-        let mut buf = [0; 1];
-        let bytes_read = self.read_data(address, &mut buf)?;
-        if bytes_read != 1 {
-            panic!("Not enough bytes read");
-        }
-        Ok(buf[0])
-    }
-
-    pub fn read_i32(&self, address: u64) -> Result<i32, &'static str> {
-        let mut buf = [0; 4];
-        let bytes_read = self.read_data(address, &mut buf)?;
-        if bytes_read != 4 {
-            panic!("Not enough bytes read");
-        }
-        let mut value = 0;
-        value |= buf[3] as i32;
-        value <<= 8;
-        value |= buf[2] as i32;
-        value <<= 8;
-        value |= buf[1] as i32;
-        value <<= 8;
-        value |= buf[0] as i32;
-        Ok(value)
-    }
-
-    pub fn read_u32(&self, address: u64) -> Result<u32, &'static str> {
-        let mut buf = [0; 4];
-        let bytes_read = self.read_data(address, &mut buf)?;
-        if bytes_read != 4 {
-            panic!("Not enough bytes read");
-        }
-        let mut value = 0;
-        value |= buf[3] as u32;
-        value <<= 8;
-        value |= buf[2] as u32;
-        value <<= 8;
-        value |= buf[1] as u32;
-        value <<= 8;
-        value |= buf[0] as u32;
-        Ok(value)
-    }
-
-    pub fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, &'static str> {
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
         let mut bytes_written;
         unsafe {
             bytes_written = mem::uninitialized();
-            let address = mem::transmute(address);
+            let remote_address = mem::transmute(address);
             let buf_addr = buf.as_ptr() as *const c_void;
-            let result = memoryapi::WriteProcessMemory(self.0, address, buf_addr, buf.len(), &mut bytes_written as *mut usize);
+            let result = memoryapi::WriteProcessMemory(self.0, remote_address, buf_addr, buf.len(), &mut bytes_written as *mut usize);
             if result == 0 {
-                return Err("Error in WriteProcessMemory");
+                return Err(Error::Access {
+                    kind: AccessKind::Write,
+                    address,
+                    requested: buf.len(),
+                    code: io::Error::last_os_error().raw_os_error(),
+                });
             }
         }
         Ok(bytes_written)
     }
-
-    pub fn write_u32(&self, address: u64, value: u32) -> Result<(), &'static str> {
-        let buf = [
-            value as u8,
-            (value >> 0x08) as u8,
-            (value >> 0x10) as u8,
-            (value >> 0x18) as u8,
-        ];
-        let bytes_written = self.write_data(address, &buf)?;
-        if bytes_written == 4 {
-            Ok(())
-        } else {
-            Err("not enough bytes written")
-        }
-    }
 }
 
 #[derive(Clone,Copy,Debug)]
 pub struct ProcessId(u32);
 
+impl ProcessId {
+    pub fn from_raw(pid: u32) -> ProcessId {
+        ProcessId(pid)
+    }
+}
+
 #[derive(Clone,Debug)]
 pub struct ProcessIterator {
     iter: IntoIter<u32>,
 }
 
 impl ProcessIterator {
-    pub fn new() -> Result<ProcessIterator, &'static str> {
+    pub fn new() -> Result<ProcessIterator, Error> {
         let mut buffer = vec![0; PROCESS_BUFFER_LEN];
 
         unsafe {
@@ -173,7 +137,7 @@ impl ProcessIterator {
             let mut returned_bytes = 0u32;
             let result = psapi::EnumProcesses(buf_ptr, (PROCESS_BUFFER_LEN * mem::size_of::<u32>()) as u32, &mut returned_bytes as *mut u32);
             if result == 0 {
-                return Err("Error in EnumProcess");
+                return Err(Error::Process { operation: "EnumProcesses", code: io::Error::last_os_error().raw_os_error() });
             }
             buffer.set_len(returned_bytes as usize / mem::size_of::<u32>());
         }
@@ -1,7 +1,9 @@
-use crate::process_reader::ProcessHandle;
-use crate::{SaveStateable, Pointer};
+use crate::{read_pointer, Bytes, FixedSize, GameMemory, SaveStateable};
+use crate::error::Error;
+use crate::state_file::StateReader;
 
-struct CollisionElement([u8;0x30]);
+#[derive(SaveStateable)]
+struct CollisionElement(#[at(0x0)] [u8;0x30]);
 
 impl CollisionElement {
     fn new() -> CollisionElement {
@@ -9,281 +11,339 @@ impl CollisionElement {
     }
 }
 
-impl SaveStateable for CollisionElement {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.0)?;
-        Ok(())
-    }
-
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.0)?;
-        Ok(())
-    }
-}
-
+#[derive(SaveStateable)]
 struct CollisionData {
+    #[at(0x0)]
     data: [u8;0xa8],
-    element_array: Pointer<CollisionElement>,
+    #[at(0xc, pointer)]
+    element_array: CollisionElement,
 }
 
 impl CollisionData {
     fn new() -> CollisionData {
         CollisionData {
             data: [0;0xa8],
-            element_array: Pointer::new(CollisionElement::new()),
+            element_array: CollisionElement::new(),
         }
     }
 }
 
-impl SaveStateable for CollisionData {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.data)?;
-        self.element_array.save(handle, address + 0xc)
-    }
-
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.data)?;
-        self.element_array.load(handle, address + 0xc)
-    }
-}
-
 // ActionStruct: 0x30 bytes
 // This also has some data in the collision stuff that we're interested as well.
 // This is why we have another 0x30 bytes we're saving.
+#[derive(SaveStateable)]
 struct ActionStruct{
+    #[at(0x0)]
     data: [u8;0x30],
-    collision_data: Pointer<CollisionData>,
+    #[at(0x2c, pointer)]
+    collision_data: CollisionData,
 }
 
 impl ActionStruct {
     fn new() -> ActionStruct {
         ActionStruct {
             data: [0;0x30],
-            collision_data: Pointer::new(CollisionData::new()),
+            collision_data: CollisionData::new(),
         }
     }
 }
 
-impl SaveStateable for ActionStruct {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.data)?;
-        self.collision_data.save(handle, address + 0x2c)
-    }
-
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.data)?;
-        self.collision_data.load(handle, address + 0x2c)
-    }
-}
-
 // GlobalMetricStruct: 0x40 bytes
-struct GlobalMetricStruct([u8;0x40]);
-
-impl SaveStateable for GlobalMetricStruct {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.0)?;
-        Ok(())
-    }
-
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.0)?;
-        Ok(())
-    }
-}
+#[derive(SaveStateable)]
+struct GlobalMetricStruct(#[at(0x0)] [u8;0x40]);
 
 // PhysicsStruct: Variable based on character
+// Character ids:
+//   0 Sonic, 1 Shadow, 2 Tails, 3 Eggman, 4 Knuckles, 5 Rouge,
+//   6 Tails (mech), 7 Eggman (mech), 8 Amy, 9 Super Sonic, 0xa Super Shadow,
+//   0xc Metal Sonic, 0xd Chao Walker, 0xe Dark Chao Walker, 0xf Tikal, 0x10 Chaos
+// The 2P-only characters reuse the struct of whoever they're based on.
 enum CharacterPhys {
     SpeedPhys([u8;0x3a0]),
     HuntPhys([u8;0x420]),
     MechPhys([u8;0x454]),
+    SuperPhys([u8;0x1d8]),
+    TailsPhys([u8;0x1f0]),
+    EggmanPhys([u8;0x1f8]),
 }
 
-impl SaveStateable for CharacterPhys {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        let character_id = handle.read_u8(address + 0x1)?;
+impl CharacterPhys {
+    // Index of the variant that holds this character's physics struct.
+    fn variant_for(character_id: u8) -> Option<u8> {
         match character_id {
-            0 | 1 => {
-                let mut buf = [0;0x3a0];
-                handle.read_data(address, &mut buf)?;
-                *self = CharacterPhys::SpeedPhys(buf);
-                Ok(())
-            }
-            4 | 5 => {
-                let mut buf = [0;0x420];
-                handle.read_data(address, &mut buf)?;
-                *self = CharacterPhys::HuntPhys(buf);
-                Ok(())
-            }
-            6 | 7 => {
-                let mut buf = [0;0x454];
-                handle.read_data(address, &mut buf)?;
-                *self = CharacterPhys::MechPhys(buf);
-                Ok(())
-            }
-            // Doesn't handle Super Sonic and Mechless, yet.
-            _ => Err("character type not supported"),
+            0 | 1 | 8 | 0xc => Some(0),
+            4 | 5 | 0xf | 0x10 => Some(1),
+            6 | 7 | 0xd | 0xe => Some(2),
+            9 | 0xa => Some(3),
+            2 => Some(4),
+            3 => Some(5),
+            _ => None,
         }
     }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        let character_id = handle.read_u8(address + 0x1)?;
+    fn variant(&self) -> u8 {
         match *self {
-            CharacterPhys::SpeedPhys(buf) => {
-                if character_id != 0 && character_id != 1 {
-                    return Err("current character does not match savestate character");
-                }
-                handle.write_data(address, &buf)?;
-            }
-            CharacterPhys::HuntPhys(buf) => {
-                if character_id != 4 && character_id != 5 {
-                    return Err("current character does not match savestate character");
-                }
-                handle.write_data(address, &buf)?;
-            }
-            CharacterPhys::MechPhys(buf) => {
-                if character_id != 6 && character_id != 7 {
-                    return Err("current character does not match savestate character");
-                }
-                handle.write_data(address, &buf)?;
-            }
+            CharacterPhys::SpeedPhys(_) => 0,
+            CharacterPhys::HuntPhys(_) => 1,
+            CharacterPhys::MechPhys(_) => 2,
+            CharacterPhys::SuperPhys(_) => 3,
+            CharacterPhys::TailsPhys(_) => 4,
+            CharacterPhys::EggmanPhys(_) => 5,
         }
-        Ok(())
     }
-}
 
-// Struct that holds info about collision with the level
-struct LevelCollision([u8;0x84]);
+    // An empty struct of the given variant.
+    fn empty(variant: u8) -> Option<CharacterPhys> {
+        match variant {
+            0 => Some(CharacterPhys::SpeedPhys([0;0x3a0])),
+            1 => Some(CharacterPhys::HuntPhys([0;0x420])),
+            2 => Some(CharacterPhys::MechPhys([0;0x454])),
+            3 => Some(CharacterPhys::SuperPhys([0;0x1d8])),
+            4 => Some(CharacterPhys::TailsPhys([0;0x1f0])),
+            5 => Some(CharacterPhys::EggmanPhys([0;0x1f8])),
+            _ => None,
+        }
+    }
 
-impl LevelCollision {
-    fn new() -> LevelCollision {
-        LevelCollision([0;0x84])
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            CharacterPhys::SpeedPhys(ref buf) => buf,
+            CharacterPhys::HuntPhys(ref buf) => buf,
+            CharacterPhys::MechPhys(ref buf) => buf,
+            CharacterPhys::SuperPhys(ref buf) => buf,
+            CharacterPhys::TailsPhys(ref buf) => buf,
+            CharacterPhys::EggmanPhys(ref buf) => buf,
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        match *self {
+            CharacterPhys::SpeedPhys(ref mut buf) => buf,
+            CharacterPhys::HuntPhys(ref mut buf) => buf,
+            CharacterPhys::MechPhys(ref mut buf) => buf,
+            CharacterPhys::SuperPhys(ref mut buf) => buf,
+            CharacterPhys::TailsPhys(ref mut buf) => buf,
+            CharacterPhys::EggmanPhys(ref mut buf) => buf,
+        }
     }
 }
 
-impl SaveStateable for LevelCollision {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.0)?;
+impl SaveStateable for CharacterPhys {
+    fn save(&mut self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        let character_id = handle.read_u8(address + 0x1)?;
+        let variant = CharacterPhys::variant_for(character_id)
+            .ok_or(Error::UnsupportedCharacter { character_id })?;
+        let mut phys = CharacterPhys::empty(variant).unwrap();
+        handle.read_exact(address, phys.bytes_mut())?;
+        *self = phys;
         Ok(())
     }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.0)?;
+    fn load(&self, handle: &dyn GameMemory, address: u64) -> Result<(), Error> {
+        let character_id = handle.read_u8(address + 0x1)?;
+        let buf = self.bytes();
+        if CharacterPhys::variant_for(character_id) != Some(self.variant()) {
+            return Err(Error::CharacterMismatch { saved: buf[1], current: character_id });
+        }
+        handle.write_all(address, buf)?;
         Ok(())
     }
+
+    // Stored as a one byte tag for the variant, followed by the struct.
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.push(self.variant());
+        out.extend_from_slice(self.bytes());
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        let mut phys = CharacterPhys::empty(input.read_u8()?)
+            .ok_or(Error::InvalidSavestate { reason: "unknown physics struct type" })?;
+        input.read_bytes(phys.bytes_mut())?;
+        *self = phys;
+        Ok(())
+    }
+}
+
+// Struct that holds info about collision with the level
+#[derive(SaveStateable)]
+struct LevelCollision(#[at(0x0)] [u8;0x84]);
+
+impl LevelCollision {
+    fn new() -> LevelCollision {
+        LevelCollision([0;0x84])
+    }
 }
 
 // Top level physics struct
+#[derive(SaveStateable)]
 struct PhysicsStruct {
+    #[at(0x0)]
     data: CharacterPhys,
-    level_collision: Pointer<LevelCollision>,
+    #[at(0x90, pointer)]
+    level_collision: LevelCollision,
 }
 
 impl PhysicsStruct {
     fn new() -> PhysicsStruct {
         PhysicsStruct {
             data: CharacterPhys::SpeedPhys([0;0x3a0]),
-            level_collision: Pointer::new(LevelCollision::new()),
+            level_collision: LevelCollision::new(),
         }
     }
 }
 
-impl SaveStateable for PhysicsStruct {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        self.data.save(handle, address)?;
-        self.level_collision.save(handle, address + 0x90)
-    }
-
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        self.data.load(handle, address)?;
-        self.level_collision.load(handle, address + 0x90)
-    }
-}
-
 // Character Task Struct
 // We don't care about all the funciton pointers. They don't change.
 // We just care about the pointers to data.
+#[derive(SaveStateable)]
 pub struct Character {
-    acs: Pointer<ActionStruct>,
-    gms: Pointer<GlobalMetricStruct>,
-    phs: Pointer<PhysicsStruct>,
+    #[at(0x34, pointer)]
+    acs: ActionStruct,
+    #[at(0x38, pointer)]
+    gms: GlobalMetricStruct,
+    #[at(0x40, pointer)]
+    phs: PhysicsStruct,
 }
 
 impl Character {
     pub fn new() -> Character {
         Character {
-            acs: Pointer(ActionStruct::new()),
-            gms: Pointer(GlobalMetricStruct([0;0x40])),
-            phs: Pointer(PhysicsStruct::new()),
+            acs: ActionStruct::new(),
+            gms: GlobalMetricStruct([0;0x40]),
+            phs: PhysicsStruct::new(),
         }
     }
-}
-
-impl SaveStateable for Character {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        self.acs.save(handle, address + 0x34)?;
-        self.gms.save(handle, address + 0x38)?;
-        self.phs.save(handle, address + 0x40)?;
-        Ok(())
-    }
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        self.acs.load(handle, address + 0x34)?;
-        self.gms.load(handle, address + 0x38)?;
-        self.phs.load(handle, address + 0x40)?;
-        Ok(())
+    // Reads the character id out of the physics struct of the character
+    // task at `address`.
+    pub fn read_id(handle: &dyn GameMemory, address: u64) -> Result<u8, Error> {
+        let physics = read_pointer(handle, address + 0x40)?;
+        handle.read_u8(physics + 0x1)
     }
 }
 
-// Array of 4 at 0x01dcff40
-// Size of each element is 0x24d8
-// We only save the first element
-// We also save a size 0x40 set of data at 0x01dcff00
+// Array of 4 at camera_elements
+// Size of each element is camera_element_size (0x24d8 on PC)
+// We only save the first element (AllPlayersUnit saves the rest in 2P)
+// We also save a size 0x40 set of data just before it, at camera
 // These are contiguous, so might as well read it all together
-pub struct Camera([u8;0x2518]);
+// (camera_size, 0x2518 on PC)
+// 0x019f3190, 0x019f31d0 and 0x019f317c may potentially be needed too,
+// but don't seem to affect savestates.
+#[derive(SaveStateable)]
+pub struct Camera(#[at(0x0)] Bytes);
 
 impl Camera {
-    pub fn new() -> Camera {
-        Camera([0;0x2518])
+    pub fn new(size: u64) -> Camera {
+        Camera(Bytes::new(size))
     }
 }
 
-impl SaveStateable for Camera {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.0)?;
-        // These values may potentially be needed, but don't seem to affect savestates.
-//        handle.read_data(0x019f3190, &mut self.1)?;
-//        handle.read_data(0x019f31d0, &mut self.2)?;
-//        handle.read_data(0x019f317c, &mut self.3)?;
-        Ok(())
-    }
+// One element of the camera array at camera_elements.
+// There's one of these for each player.
+#[derive(SaveStateable)]
+pub struct CameraElement(#[at(0x0)] Bytes);
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.0)?;
-//        handle.write_data(0x019f3190, &self.1)?;
-//        handle.write_data(0x019f31d0, &self.2)?;
-//        handle.write_data(0x019f317c, &self.3)?;
-        Ok(())
+impl CameraElement {
+    pub fn new(size: u64) -> CameraElement {
+        CameraElement(Bytes::new(size))
     }
 }
 
-// PastPositionTable: 0xc000 bytes
+// PastPositionTable: past_position_table_size bytes (0xc00 on PC)
 // A set of 0x100 3vecs.
-pub struct PastPositionTable([u8;0xc00]);
+#[derive(SaveStateable)]
+pub struct PastPositionTable(#[at(0x0)] Bytes);
 
 impl PastPositionTable {
-    pub fn new() -> PastPositionTable {
-        PastPositionTable([0;0xc00])
+    pub fn new(size: u64) -> PastPositionTable {
+        PastPositionTable(Bytes::new(size))
     }
 }
 
-impl SaveStateable for PastPositionTable {
-    fn save(&mut self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.read_data(address, &mut self.0)?;
-        Ok(())
+
+// Position/speed vector, three floats (NJS_VECTOR).
+#[derive(Clone,Copy,Debug,PartialEq,Default)]
+#[derive(SaveStateable)]
+pub struct Vec3 {
+    #[at(0x0)]
+    pub x: f32,
+    #[at(0x4)]
+    pub y: f32,
+    #[at(0x8)]
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3 { x, y, z }
     }
+}
 
-    fn load(&self, handle: &ProcessHandle, address: u64) -> Result<(), &'static str> {
-        handle.write_data(address, &self.0)?;
-        Ok(())
+impl FixedSize for Vec3 {
+    const SIZE: u64 = 0xc;
+    const ALIGN: u64 = 4;
+}
+
+// Angles are BAMS: 0x10000 is a full turn. The game only looks at the low
+// 16 bits, but stores them as 32-bit ints (Rotation).
+pub const BAMS_PER_TURN: i32 = 0x10000;
+
+pub fn bams_to_degrees(angle: i32) -> f32 {
+    angle as f32 * 360.0 / BAMS_PER_TURN as f32
+}
+
+pub fn degrees_to_bams(degrees: f32) -> i32 {
+    (degrees * BAMS_PER_TURN as f32 / 360.0).round() as i32
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+#[derive(SaveStateable)]
+pub struct Rotation {
+    #[at(0x0)]
+    pub x: i32,
+    #[at(0x4)]
+    pub y: i32,
+    #[at(0x8)]
+    pub z: i32,
+}
+
+impl Rotation {
+    pub fn new(x: i32, y: i32, z: i32) -> Rotation {
+        Rotation { x, y, z }
     }
 }
+
+impl FixedSize for Rotation {
+    const SIZE: u64 = 0xc;
+    const ALIGN: u64 = 4;
+}
+
+// One entry in the level's dynamic collision list: an object that has
+// added some of its own collision to the stage (moving platforms, item
+// boxes, containers...). The list is dyncol_max of these (0x400 on PC) at
+// dyncol_list, with the number in use at dyncol_count.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+#[derive(SaveStateable)]
+pub struct DynColEntry {
+    #[at(0x0)]
+    pub flags: u32,
+    // The object's collision model.
+    #[at(0x4)]
+    pub model: u32,
+    // The object's task.
+    #[at(0x8)]
+    pub task: u32,
+}
+
+impl DynColEntry {
+    // Whether `other` is for the same object and model.
+    pub fn same_object(&self, other: &DynColEntry) -> bool {
+        self.model == other.model && self.task == other.task
+    }
+}
+
+impl FixedSize for DynColEntry {
+    const SIZE: u64 = 0xc;
+    const ALIGN: u64 = 4;
+}
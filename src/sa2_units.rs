@@ -1,26 +1,212 @@
-use crate::{SaveStateUnit, SaveStateable, Pointer};
-use crate::process_reader::ProcessHandle;
-use crate::sa2_structures::{Character, Camera, PastPositionTable};
+use std::collections::HashSet;
+
+use crate::{Bytes, FixedSize, GameMemory, SaveStateUnit, SaveStateable, Pointer};
+use crate::addresses::Addresses;
+use crate::batch::MemoryRange;
+use crate::error::{Error, ResultExt};
+use crate::load_scheduler::LoadSchedule;
+use crate::state_file::StateReader;
+use crate::sa2_structures::{Character, Camera, CameraElement, DynColEntry, PastPositionTable, Vec3};
 
 pub struct CharacterUnit {
     character: Pointer<Character>,
+    address: u64,
 }
 
 impl CharacterUnit {
-    pub fn new() -> CharacterUnit {
+    pub fn new(addresses: &Addresses) -> CharacterUnit {
         CharacterUnit {
             character: Pointer::new(Character::new()),
+            address: addresses.character_tasks,
         }
     }
 }
 
 impl SaveStateUnit for CharacterUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        self.character.save(handle, 0x01dea6e0)
+    fn name(&self) -> &'static str {
+        "CharacterUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.character.save(handle, self.address).context("CharacterUnit")
+    }
+
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.character.load(handle, self.address).context("CharacterUnit")
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        self.character.write_state(out)
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        self.character.read_state(input)
+    }
+
+    // The game redoes some of the character's collision on the frame after
+    // a load, so it gets loaded again once that's happened.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::AfterFrames(1)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![MemoryRange::new(self.address, 4)]
+    }
+}
+
+// Every player's character and camera entry, for 2P mode.
+// Character task pointers are an array of 4 at character_tasks, and the
+// camera entries are the array of 4 at camera_elements. Player 1's camera
+// entry is also saved by CameraUnit, which is harmless.
+pub struct AllPlayersUnit {
+    players: Vec<Option<PlayerState>>,
+    character_tasks: u64,
+    camera_elements: u64,
+    camera_element_size: u64,
+}
+
+struct PlayerState {
+    character_id: u8,
+    character: Character,
+    camera: CameraElement,
+}
+
+const PLAYER_COUNT: usize = 4;
+const PLAYER_NAMES: [&str; PLAYER_COUNT] = ["player1", "player2", "player3", "player4"];
+
+impl AllPlayersUnit {
+    pub fn new(addresses: &Addresses) -> AllPlayersUnit {
+        AllPlayersUnit {
+            players: (0..PLAYER_COUNT).map(|_| None).collect(),
+            character_tasks: addresses.character_tasks,
+            camera_elements: addresses.camera_elements,
+            camera_element_size: addresses.camera_element_size,
+        }
+    }
+
+    fn character_address(&self, player: usize) -> u64 {
+        self.character_tasks + 4 * player as u64
     }
 
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        self.character.load(handle, 0x01dea6e0)
+    fn camera_element_address(&self, player: usize) -> u64 {
+        self.camera_elements + self.camera_element_size * player as u64
+    }
+
+    // The character id in each player slot, or None if the slot's empty.
+    fn read_player_ids(&self, handle: &dyn GameMemory) -> Result<Vec<Option<u8>>, Error> {
+        let mut ids = Vec::with_capacity(PLAYER_COUNT);
+        for (player, name) in PLAYER_NAMES.iter().enumerate() {
+            let task = handle.read_ptr(self.character_address(player)).context(name)?;
+            if task == 0 {
+                ids.push(None);
+            } else {
+                ids.push(Some(Character::read_id(handle, task).context(name)?));
+            }
+        }
+        Ok(ids)
+    }
+}
+
+impl SaveStateUnit for AllPlayersUnit {
+    fn name(&self) -> &'static str {
+        "AllPlayersUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        let ids = self.read_player_ids(handle).context("AllPlayersUnit")?;
+        for (player, id) in ids.into_iter().enumerate() {
+            self.players[player] = match id {
+                Some(character_id) => {
+                    let mut state = PlayerState {
+                        character_id,
+                        character: Character::new(),
+                        camera: CameraElement::new(self.camera_element_size),
+                    };
+                    state.character.save(handle, handle.read_ptr(self.character_address(player))?)
+                        .context(PLAYER_NAMES[player])
+                        .context("AllPlayersUnit")?;
+                    state.camera.save(handle, self.camera_element_address(player))
+                        .context(PLAYER_NAMES[player])
+                        .context("AllPlayersUnit")?;
+                    Some(state)
+                }
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    // Checks every player before writing anything, so a mismatch in
+    // player 2 doesn't leave player 1 half-loaded.
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        let ids = self.read_player_ids(handle).context("AllPlayersUnit")?;
+        for (player, (state, current)) in self.players.iter().zip(ids).enumerate() {
+            let saved = state.as_ref().map(|state| state.character_id);
+            if saved != current {
+                return Err(Error::PlayerMismatch { player: player + 1, saved, current })
+                    .context("AllPlayersUnit");
+            }
+        }
+
+        for (player, state) in self.players.iter().enumerate() {
+            if let Some(ref state) = *state {
+                state.character.load(handle, handle.read_ptr(self.character_address(player))?)
+                    .context(PLAYER_NAMES[player])
+                    .context("AllPlayersUnit")?;
+                state.camera.load(handle, self.camera_element_address(player))
+                    .context(PLAYER_NAMES[player])
+                    .context("AllPlayersUnit")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Each player is a presence byte, then the character id, character
+    // and camera entry if they're there.
+    fn write_state(&self, out: &mut Vec<u8>) {
+        for state in self.players.iter() {
+            match *state {
+                Some(ref state) => {
+                    out.push(1);
+                    out.push(state.character_id);
+                    state.character.write_state(out);
+                    state.camera.write_state(out);
+                }
+                None => out.push(0),
+            }
+        }
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        for player in self.players.iter_mut() {
+            *player = match input.read_u8()? {
+                0 => None,
+                1 => {
+                    let mut state = PlayerState {
+                        character_id: input.read_u8()?,
+                        character: Character::new(),
+                        camera: CameraElement::new(self.camera_element_size),
+                    };
+                    state.character.read_state(input)?;
+                    state.camera.read_state(input)?;
+                    Some(state)
+                }
+                _ => return Err(Error::InvalidSavestate { reason: "bad player presence flag" }),
+            };
+        }
+        Ok(())
+    }
+
+    // Same as CharacterUnit.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::AfterFrames(1)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![
+            MemoryRange::new(self.character_tasks, 4 * PLAYER_COUNT as u64),
+            MemoryRange::new(self.camera_elements, self.camera_element_size * PLAYER_COUNT as u64),
+        ]
     }
 }
 
@@ -31,100 +217,643 @@ pub struct CameraUnit {
     past_positions_idx: u8,
     camera_cons_past_positions: PastPositionTable,
     camera_cons_past_positions_idx: u8,
+    addresses: Addresses,
 }
 
 impl CameraUnit {
-    pub fn new() -> CameraUnit {
+    pub fn new(addresses: &Addresses) -> CameraUnit {
         CameraUnit {
-            camera: Camera::new(),
-            past_positions: Pointer::new(PastPositionTable::new()),
-            past_rotations: Pointer::new(PastPositionTable::new()),
+            camera: Camera::new(addresses.camera_size),
+            past_positions: Pointer::new(PastPositionTable::new(addresses.past_position_table_size)),
+            past_rotations: Pointer::new(PastPositionTable::new(addresses.past_position_table_size)),
             past_positions_idx: 0,
-            camera_cons_past_positions: PastPositionTable::new(),
+            camera_cons_past_positions: PastPositionTable::new(addresses.past_position_table_size),
             camera_cons_past_positions_idx: 0,
+            addresses: *addresses,
         }
     }
 }
 
 impl SaveStateUnit for CameraUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        self.camera.save(handle, 0x01dcff00)?;
-        self.past_positions.save(handle, 0x01a5a234)?;
-        self.past_rotations.save(handle, 0x01a5a238)?;
-        self.past_positions_idx.save(handle, 0x01945910)?;
-        self.camera_cons_past_positions.save(handle, 0x019f1740)?;
-        self.camera_cons_past_positions_idx.save(handle, 0x019f173c)
+    fn name(&self) -> &'static str {
+        "CameraUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.camera.save(handle, self.addresses.camera).context("CameraUnit.camera")?;
+        self.past_positions.save(handle, self.addresses.past_positions_ptr).context("CameraUnit.past_positions")?;
+        self.past_rotations.save(handle, self.addresses.past_rotations_ptr).context("CameraUnit.past_rotations")?;
+        self.past_positions_idx.save(handle, self.addresses.past_positions_idx).context("CameraUnit.past_positions_idx")?;
+        self.camera_cons_past_positions.save(handle, self.addresses.camera_cons_past_positions).context("CameraUnit.camera_cons_past_positions")?;
+        self.camera_cons_past_positions_idx.save(handle, self.addresses.camera_cons_past_positions_idx).context("CameraUnit.camera_cons_past_positions_idx")
+    }
+
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.camera.load(handle, self.addresses.camera).context("CameraUnit.camera")?;
+        self.past_positions.load(handle, self.addresses.past_positions_ptr).context("CameraUnit.past_positions")?;
+        self.past_rotations.load(handle, self.addresses.past_rotations_ptr).context("CameraUnit.past_rotations")?;
+        self.past_positions_idx.load(handle, self.addresses.past_positions_idx).context("CameraUnit.past_positions_idx")?;
+        self.camera_cons_past_positions.load(handle, self.addresses.camera_cons_past_positions).context("CameraUnit.camera_cons_past_positions")?;
+        self.camera_cons_past_positions_idx.load(handle, self.addresses.camera_cons_past_positions_idx).context("CameraUnit.camera_cons_past_positions_idx")
     }
 
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        self.camera.load(handle, 0x01dcff00)?;
-        self.past_positions.load(handle, 0x01a5a234)?;
-        self.past_rotations.load(handle, 0x01a5a238)?;
-        self.past_positions_idx.load(handle, 0x01945910)?;
-        self.camera_cons_past_positions.load(handle, 0x019f1740)?;
-        self.camera_cons_past_positions_idx.load(handle, 0x019f173c)
+    fn write_state(&self, out: &mut Vec<u8>) {
+        self.camera.write_state(out);
+        self.past_positions.write_state(out);
+        self.past_rotations.write_state(out);
+        self.past_positions_idx.write_state(out);
+        self.camera_cons_past_positions.write_state(out);
+        self.camera_cons_past_positions_idx.write_state(out);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        self.camera.read_state(input)?;
+        self.past_positions.read_state(input)?;
+        self.past_rotations.read_state(input)?;
+        self.past_positions_idx.read_state(input)?;
+        self.camera_cons_past_positions.read_state(input)?;
+        self.camera_cons_past_positions_idx.read_state(input)
+    }
+
+    // Follows the character, so it's reloaded along with it.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::AfterFrames(1)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![
+            MemoryRange::new(self.addresses.camera, self.addresses.camera_size),
+            MemoryRange::new(self.addresses.past_positions_ptr, 4),
+            MemoryRange::new(self.addresses.past_rotations_ptr, 4),
+            MemoryRange::new(self.addresses.past_positions_idx, 1),
+            MemoryRange::new(self.addresses.camera_cons_past_positions, self.addresses.past_position_table_size),
+            MemoryRange::new(self.addresses.camera_cons_past_positions_idx, 1),
+        ]
     }
 }
 
-pub struct TimeUnit([u8;0x3]);
+pub struct TimeUnit {
+    data: [u8;0x3],
+    address: u64,
+}
 
 impl TimeUnit {
-    pub fn new() -> TimeUnit {
-        TimeUnit([0;0x3])
+    pub fn new(addresses: &Addresses) -> TimeUnit {
+        TimeUnit {
+            data: [0;0x3],
+            address: addresses.time,
+        }
     }
 }
 
 impl SaveStateUnit for TimeUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.read_data(0x0174AFDB, &mut self.0)?;
+    fn name(&self) -> &'static str {
+        "TimeUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        handle.read_exact(self.address, &mut self.data).context("TimeUnit")?;
         Ok(())
     }
 
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.write_data(0x0174AFDB, &self.0)?;
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        handle.write_all(self.address, &self.data).context("TimeUnit")?;
         Ok(())
     }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        input.read_bytes(&mut self.data)
+    }
+
+    // Reloaded with the character so the timer stays in step.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::AfterFrames(1)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![MemoryRange::new(self.address, self.data.len() as u64)]
+    }
 }
 
-pub struct GravityUnit([u8;0xc]);
+pub struct GravityUnit {
+    data: [u8;0xc],
+    address: u64,
+}
 
 impl GravityUnit {
-    pub fn new() -> GravityUnit {
-        GravityUnit([0;0xc])
+    pub fn new(addresses: &Addresses) -> GravityUnit {
+        GravityUnit {
+            data: [0;0xc],
+            address: addresses.gravity,
+        }
     }
 }
 
 impl SaveStateUnit for GravityUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.read_data(0x01DE94A0, &mut self.0)?;
+    fn name(&self) -> &'static str {
+        "GravityUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        handle.read_exact(self.address, &mut self.data).context("GravityUnit")?;
         Ok(())
     }
 
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.write_data(0x01DE94A0, &self.0)?;
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        handle.write_all(self.address, &self.data).context("GravityUnit")?;
         Ok(())
     }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        input.read_bytes(&mut self.data)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![MemoryRange::new(self.address, self.data.len() as u64)]
+    }
 }
 
-// Crashes the game. :(
-pub struct LevelCollisionUnit([u8;0x3000], [u8;0x2]);
+// Which of CountersUnit's values get put back on load. Everything is
+// always saved, so a savestate made with some of these off still has them.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct CounterToggles {
+    pub rings: bool,
+    pub lives: bool,
+    pub score: bool,
+}
+
+impl CounterToggles {
+    pub fn all() -> CounterToggles {
+        CounterToggles {
+            rings: true,
+            lives: true,
+            score: true,
+        }
+    }
+}
+
+// Ring count, lives and score.
+// Rings are an i16 per player, lives an i8 per player. Score is player 1's.
+pub struct CountersUnit {
+    rings: [i16; 2],
+    lives: [i8; 2],
+    score: u32,
+    toggles: CounterToggles,
+    addresses: Addresses,
+}
+
+impl CountersUnit {
+    pub fn new(addresses: &Addresses, toggles: CounterToggles) -> CountersUnit {
+        CountersUnit {
+            rings: [0; 2],
+            lives: [0; 2],
+            score: 0,
+            toggles,
+            addresses: *addresses,
+        }
+    }
+}
+
+impl SaveStateUnit for CountersUnit {
+    fn name(&self) -> &'static str {
+        "CountersUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.rings.save(handle, self.addresses.rings).context("CountersUnit.rings")?;
+        self.lives.save(handle, self.addresses.lives).context("CountersUnit.lives")?;
+        self.score.save(handle, self.addresses.score).context("CountersUnit.score")
+    }
+
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        if self.toggles.rings {
+            self.rings.load(handle, self.addresses.rings).context("CountersUnit.rings")?;
+        }
+        if self.toggles.lives {
+            self.lives.load(handle, self.addresses.lives).context("CountersUnit.lives")?;
+        }
+        if self.toggles.score {
+            self.score.load(handle, self.addresses.score).context("CountersUnit.score")?;
+        }
+        Ok(())
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        self.rings.write_state(out);
+        self.lives.write_state(out);
+        self.score.write_state(out);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        self.rings.read_state(input)?;
+        self.lives.read_state(input)?;
+        self.score.read_state(input)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![
+            MemoryRange::new(self.addresses.rings, 4),
+            MemoryRange::new(self.addresses.lives, 2),
+            MemoryRange::new(self.addresses.score, 4),
+        ]
+    }
+
+    fn optional(&self) -> bool {
+        true
+    }
+}
+
+// Saves and restores the seed of the C runtime's rand(), which the game
+// uses for nearly all of its randomness (enemy behavior, item box
+// contents, ...). With a lock seed, every load writes that seed instead, so
+// each attempt plays out the same way whichever slot it comes from.
+pub struct RngUnit {
+    seed: u32,
+    lock: Option<u32>,
+    address: u64,
+}
+
+impl RngUnit {
+    pub fn new(addresses: &Addresses, lock: Option<u32>) -> RngUnit {
+        RngUnit {
+            seed: 0,
+            lock,
+            address: addresses.rng_seed,
+        }
+    }
+}
+
+impl SaveStateUnit for RngUnit {
+    fn name(&self) -> &'static str {
+        "RngUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.seed.save(handle, self.address).context("RngUnit")
+    }
+
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.lock.unwrap_or(self.seed).load(handle, self.address).context("RngUnit")
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        self.seed.write_state(out);
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        self.seed.read_state(input)
+    }
+
+    // Anything random that happens before the character is reloaded
+    // shouldn't count, so the seed is reloaded along with it.
+    fn load_schedule(&self) -> LoadSchedule {
+        LoadSchedule::AfterFrames(1)
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![MemoryRange::new(self.address, 4)]
+    }
+
+    fn optional(&self) -> bool {
+        true
+    }
+}
+
+// The list is copied in one go rather than an entry at a time. Every
+// DynColEntry field is a u32, so it's converted to and from the game's
+// byte order a word at a time.
+fn reorder_words(handle: &dyn GameMemory, data: &mut [u8]) {
+    for word in data.chunks_mut(4) {
+        handle.byte_order().reorder(word);
+    }
+}
+
+// The level's dynamic collision list.
+// Copying the whole list back used to crash the game: entries for objects
+// that were destroyed since the save point at freed memory. So loading
+// only reorders the entries that are in the list now, back into the saved
+// order and with their saved flags. Saved and live entries are paired off
+// one to one, so an object with several entries keeps all of them, and live
+// entries the savestate doesn't know about stay as they are, after the
+// rest. The list keeps its length and its pointers; nothing is added or
+// removed.
+// Not tried in game yet, so main only loads it with --restore-collision.
+pub struct LevelCollisionUnit {
+    entries: Vec<DynColEntry>,
+    list: u64,
+    count: u64,
+    max: usize,
+}
 
 impl LevelCollisionUnit {
-    pub fn new() -> LevelCollisionUnit {
-        LevelCollisionUnit([0;0x3000], [0;0x2])
+    pub fn new(addresses: &Addresses) -> LevelCollisionUnit {
+        LevelCollisionUnit {
+            entries: Vec::new(),
+            list: addresses.dyncol_list,
+            count: addresses.dyncol_count,
+            max: addresses.dyncol_max as usize,
+        }
+    }
+
+    fn read_entries(&self, handle: &dyn GameMemory) -> Result<Vec<DynColEntry>, Error> {
+        let count = (handle.read_u16(self.count)? as usize).min(self.max);
+
+        let mut data = vec![0; count * DynColEntry::SIZE as usize];
+        handle.read_exact(self.list, &mut data)?;
+        reorder_words(handle, &mut data);
+        let mut reader = StateReader::new(&data);
+        let mut entries = vec![DynColEntry::default(); count];
+        for entry in entries.iter_mut() {
+            entry.read_state(&mut reader)?;
+        }
+        Ok(entries)
     }
 }
 
 impl SaveStateUnit for LevelCollisionUnit {
-    fn save(&mut self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.read_data(0x01a5a2dc, &mut self.0)?;
-        handle.read_data(0x01de9484, &mut self.1)?;
+    fn name(&self) -> &'static str {
+        "LevelCollisionUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        self.entries = self.read_entries(handle).context("LevelCollisionUnit")?;
         Ok(())
     }
 
-    fn load(&self, handle: &ProcessHandle) -> Result<(), &'static str> {
-        handle.write_data(0x01a5a2dc, &self.0)?;
-        handle.write_data(0x01de9484, &self.1)?;
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        let live = self.read_entries(handle).context("LevelCollisionUnit")?;
+
+        let mut unmatched: Vec<Option<DynColEntry>> = live.iter().cloned().map(Some).collect();
+        let mut entries = Vec::with_capacity(live.len());
+        for saved in self.entries.iter().filter(|saved| saved.task != 0) {
+            let partner = unmatched.iter_mut()
+                .find(|entry| entry.is_some_and(|entry| entry.same_object(saved)));
+            if let Some(partner) = partner {
+                let entry = partner.take().unwrap();
+                entries.push(DynColEntry { flags: saved.flags, ..entry });
+            }
+        }
+        entries.extend(unmatched.into_iter().flatten());
+
+        let mut data = Vec::new();
+        for entry in entries.iter() {
+            entry.write_state(&mut data);
+        }
+        reorder_words(handle, &mut data);
+        handle.write_all(self.list, &data).context("LevelCollisionUnit")
+    }
+
+    // Stored as a u16 count followed by the entries.
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        for entry in self.entries.iter() {
+            entry.write_state(out);
+        }
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        let count = input.read_u16()? as usize;
+        if count > self.max {
+            return Err(Error::InvalidSavestate { reason: "too many collision entries" });
+        }
+        let mut entries = vec![DynColEntry::default(); count];
+        for entry in entries.iter_mut() {
+            entry.read_state(input)?;
+        }
+        self.entries = entries;
         Ok(())
     }
+
+    // The whole list, whatever the count is.
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![
+            MemoryRange::new(self.list, self.max as u64 * DynColEntry::SIZE),
+            MemoryRange::new(self.count, 2),
+        ]
+    }
+
+    fn optional(&self) -> bool {
+        true
+    }
+}
+
+pub const OBJECT_LIST_COUNT: usize = 8;
+// Stops a corrupt (or mid-update) list from being walked forever.
+const MAX_OBJECTS: usize = 0x1000;
+
+// Objects are told apart by the SET (stage layout) entry they were spawned
+// from: its object type id and position. Objects that didn't come from the
+// SET file (players, effects, projectiles) are skipped.
+#[derive(Clone,Copy,Debug)]
+struct ObjectKey {
+    id: u16,
+    position: Vec3,
+}
+
+impl ObjectKey {
+    fn empty() -> ObjectKey {
+        ObjectKey {
+            id: 0,
+            position: Vec3::default(),
+        }
+    }
+
+    // Reads the key out of the SET entry that the SET data at `set_data`
+    // points to.
+    fn read(handle: &dyn GameMemory, set_data: u64) -> Result<Option<ObjectKey>, Error> {
+        let entry = handle.read_ptr(set_data + 0x8)?;
+        if entry == 0 {
+            return Ok(None);
+        }
+        let mut key = ObjectKey::empty();
+        key.id.save(handle, entry)?;
+        key.position.save(handle, entry + 0x8)?;
+        Ok(Some(key))
+    }
+}
+
+// Positions are compared bit for bit, so NaNs still match themselves.
+impl PartialEq for ObjectKey {
+    fn eq(&self, other: &ObjectKey) -> bool {
+        let bits = |position: &Vec3| [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+        self.id == other.id && bits(&self.position) == bits(&other.position)
+    }
+}
+
+// The start of an object's SET data: its load count (+0x0) and flags
+// (+0x2). This is what tells the game whether the object has been destroyed
+// or still needs spawning. The pointer to its task at +0x4 is never saved:
+// the task a load would put back has been freed, or replaced by another.
+type SpawnState = [u8;0x4];
+
+struct ObjectState {
+    key: ObjectKey,
+    // Where its SET data was. This lives as long as the stage does, unlike
+    // the task.
+    set_data: u64,
+    spawn: SpawnState,
+    // The start of its EntityData1, up to the collision pointer: action,
+    // status, rotation, position and scale.
+    data: Bytes,
+}
+
+// An object in one of the object lists.
+struct LiveObject {
+    key: ObjectKey,
+    set_data: u64,
+    data1: u64,
+}
+
+// Every object in the object lists at `lists` that came from the SET file.
+// Tasks are linked through the pointer at +0x4, with their SET data at
+// +0x30 (which points to the SET entry at +0x8) and EntityData1 at +0x34.
+// SET entries have the type id at +0x0 and the position at +0x8.
+fn read_objects(handle: &dyn GameMemory, lists: u64) -> Result<Vec<LiveObject>, Error> {
+    let mut objects = Vec::new();
+    let mut visited = HashSet::new();
+    for list in 0..OBJECT_LIST_COUNT {
+        let mut task = handle.read_ptr(lists + 4 * list as u64)?;
+        // Lists can be circular, so stop at anything we've already seen.
+        while task != 0 && visited.len() < MAX_OBJECTS && visited.insert(task) {
+            let set_data = handle.read_ptr(task + 0x30)?;
+            let data1 = handle.read_ptr(task + 0x34)?;
+            if set_data != 0 && data1 != 0 {
+                if let Some(key) = ObjectKey::read(handle, set_data)? {
+                    objects.push(LiveObject { key, set_data, data1 });
+                }
+            }
+
+            task = handle.read_ptr(task + 0x4)?;
+        }
+    }
+    Ok(objects)
+}
+
+// State of the stage's objects: enemies, item boxes, containers, springs...
+// Objects that still exist when loading get their spawn state and data put
+// back. Anything destroyed since the save has had its task freed, but its
+// SET data is still there, so that gets the saved spawn state with the load
+// count cleared and the game spawns the object again once you're near it.
+// Anything that's appeared since the save is left alone.
+pub struct LevelObjectsUnit {
+    objects: Vec<ObjectState>,
+    lists: u64,
+    data_size: u64,
+}
+
+impl LevelObjectsUnit {
+    pub fn new(addresses: &Addresses) -> LevelObjectsUnit {
+        LevelObjectsUnit {
+            objects: Vec::new(),
+            lists: addresses.object_lists,
+            data_size: addresses.object_data_size,
+        }
+    }
+}
+
+// Puts the spawn state of a destroyed object back, as long as its SET data
+// is still there and for the same entry. It isn't if the stage has been
+// reloaded since, in which case the object is already back.
+fn respawn(handle: &dyn GameMemory, state: &ObjectState) -> Result<(), Error> {
+    match ObjectKey::read(handle, state.set_data) {
+        Ok(Some(key)) if key == state.key => {
+            let mut spawn = state.spawn;
+            spawn[0] = 0;
+            spawn.load(handle, state.set_data)
+        }
+        _ => Ok(()),
+    }
+}
+
+impl SaveStateUnit for LevelObjectsUnit {
+    fn name(&self) -> &'static str {
+        "LevelObjectsUnit"
+    }
+
+    fn save(&mut self, handle: &dyn GameMemory) -> Result<(), Error> {
+        let mut objects = Vec::new();
+        for object in read_objects(handle, self.lists).context("LevelObjectsUnit")? {
+            let mut state = ObjectState {
+                key: object.key,
+                set_data: object.set_data,
+                spawn: [0;0x4],
+                data: Bytes::new(self.data_size),
+            };
+            state.spawn.save(handle, object.set_data).context("LevelObjectsUnit")?;
+            state.data.save(handle, object.data1).context("LevelObjectsUnit")?;
+            objects.push(state);
+        }
+        self.objects = objects;
+        Ok(())
+    }
+
+    fn load(&self, handle: &dyn GameMemory) -> Result<(), Error> {
+        let mut live = read_objects(handle, self.lists).context("LevelObjectsUnit")?;
+        // Two objects can share a key (stacked item boxes), so match them
+        // up in list order and don't use a live one twice.
+        for state in self.objects.iter() {
+            match live.iter().position(|object| object.key == state.key) {
+                Some(idx) => {
+                    let object = live.remove(idx);
+                    state.spawn.load(handle, object.set_data).context("LevelObjectsUnit")?;
+                    state.data.load(handle, object.data1).context("LevelObjectsUnit")?;
+                }
+                None => respawn(handle, state).context("LevelObjectsUnit")?,
+            }
+        }
+        Ok(())
+    }
+
+    // Stored as a u32 count, then for each object its type id, position,
+    // SET data address, spawn state and data.
+    fn write_state(&self, out: &mut Vec<u8>) {
+        (self.objects.len() as u32).write_state(out);
+        for state in self.objects.iter() {
+            state.key.id.write_state(out);
+            state.key.position.write_state(out);
+            (state.set_data as u32).write_state(out);
+            state.spawn.write_state(out);
+            state.data.write_state(out);
+        }
+    }
+
+    fn read_state(&mut self, input: &mut StateReader) -> Result<(), Error> {
+        let count = input.read_u32()? as usize;
+        if count > MAX_OBJECTS {
+            return Err(Error::InvalidSavestate { reason: "too many objects" });
+        }
+        let mut objects = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut state = ObjectState {
+                key: ObjectKey::empty(),
+                set_data: 0,
+                spawn: [0;0x4],
+                data: Bytes::new(self.data_size),
+            };
+            state.key.id.read_state(input)?;
+            state.key.position.read_state(input)?;
+            state.set_data = input.read_u32()? as u64;
+            state.spawn.read_state(input)?;
+            state.data.read_state(input)?;
+            objects.push(state);
+        }
+        self.objects = objects;
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<MemoryRange> {
+        vec![MemoryRange::new(self.lists, 4 * OBJECT_LIST_COUNT as u64)]
+    }
+
+    fn optional(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,348 @@
+// The tool's main loop, minus the console and the game process.
+//
+// A Session has the savestate slots, the button bindings and the load
+// scheduler, and poll() does one pass over the game: reads the buttons,
+// and saves, loads or switches slots when they're pressed. main runs it
+// against the game, and a replayed trace can run it without one (see
+// trace.rs). Anything it has to tell the user is kept until
+// take_messages(), so the library doesn't print.
+//
+// Slots are written to (and can be read back from) savestate_slotN.ovst
+// files in the session's slot directory. A session without one keeps its
+// slots in memory only, which is what replays use so they don't depend on
+// or overwrite the user's files.
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{GameMemory, SaveStateUnit};
+use crate::addresses::Addresses;
+use crate::batch::{unit_regions, Batch};
+use crate::bindings::{Action, Bindings};
+use crate::error::Error;
+use crate::game_state::{GameState, StageClock};
+use crate::load_scheduler::LoadScheduler;
+use crate::sa2_units::{AllPlayersUnit, CharacterUnit, CameraUnit, CounterToggles, CountersUnit, TimeUnit, GravityUnit, LevelCollisionUnit, LevelObjectsUnit, RngUnit};
+use crate::state_file::{self, StateHeader};
+
+pub const SLOT_COUNT: usize = 10;
+
+// Which units every slot has, and how they load.
+#[derive(Clone,Copy,Debug)]
+pub struct UnitOptions {
+    // Save every player's character and camera instead of just player 1's
+    // character.
+    pub all_players: bool,
+    pub counters: CounterToggles,
+    // Save and load the RNG seed. Its address isn't checked against every
+    // build yet.
+    pub restore_rng: bool,
+    // Seed to write on every load instead of the saved one.
+    pub rng_lock: Option<u32>,
+    // Save and load the level's dynamic collision list. Not tried in game
+    // yet.
+    pub restore_collision: bool,
+}
+
+impl UnitOptions {
+    pub fn new() -> UnitOptions {
+        UnitOptions {
+            all_players: false,
+            counters: CounterToggles::all(),
+            restore_rng: false,
+            rng_lock: None,
+            restore_collision: false,
+        }
+    }
+}
+
+// One savestate, along with the stage it was made in.
+pub struct Slot {
+    units: Vec<Rc<dyn SaveStateUnit>>,
+    // Units the slot's file had no chunk for, which loading skips until
+    // the next save.
+    pub missing: Vec<&'static str>,
+    pub level: u32,
+    pub valid: bool,
+}
+
+impl Slot {
+    pub fn new(options: &UnitOptions, addresses: &Addresses) -> Slot {
+        let character_unit: Rc<dyn SaveStateUnit> = if options.all_players {
+            Rc::new(AllPlayersUnit::new(addresses))
+        } else {
+            Rc::new(CharacterUnit::new(addresses))
+        };
+        let mut units = vec![
+            character_unit,
+            Rc::new(CameraUnit::new(addresses)),
+            Rc::new(TimeUnit::new(addresses)),
+            Rc::new(GravityUnit::new(addresses)),
+        ];
+        if options.restore_collision {
+            units.push(Rc::new(LevelCollisionUnit::new(addresses)));
+        }
+        units.push(Rc::new(CountersUnit::new(addresses, options.counters)));
+        units.push(Rc::new(LevelObjectsUnit::new(addresses)));
+        if options.restore_rng {
+            units.push(Rc::new(RngUnit::new(addresses, options.rng_lock)));
+        }
+        Slot {
+            units,
+            missing: Vec::new(),
+            level: 0,
+            valid: false,
+        }
+    }
+
+    // Only trust the savestate if every unit saved completely. Returns the
+    // errors from the units that didn't.
+    pub fn save(&mut self, handle: &dyn GameMemory, level: u32) -> Vec<Error> {
+        self.level = level;
+        self.missing.clear();
+        let batch = Batch::new(handle);
+        batch.prefetch(&unit_regions(&self.unit_refs()));
+        let mut errors = Vec::new();
+        for unit in self.units.iter_mut() {
+            if let Err(err) = Rc::get_mut(unit).unwrap().save(&batch) {
+                errors.push(err);
+            }
+        }
+        self.valid = errors.is_empty();
+        errors
+    }
+
+    pub fn unit_refs(&self) -> Vec<&dyn SaveStateUnit> {
+        self.units.iter().map(|unit| &**unit).collect()
+    }
+
+    // The units a load writes.
+    pub fn load_refs(&self) -> Vec<&dyn SaveStateUnit> {
+        self.units.iter()
+            .filter(|unit| !self.missing.contains(&unit.name()))
+            .map(|unit| &**unit)
+            .collect()
+    }
+
+    pub fn write_file(&self, path: &Path, header: &StateHeader) -> Result<(), Error> {
+        state_file::write_file(path, header, &self.unit_refs())
+    }
+
+    pub fn read_file(&mut self, path: &Path) -> Result<StateHeader, Error> {
+        let mut unit_refs: Vec<&mut dyn SaveStateUnit> = Vec::new();
+        for unit in self.units.iter_mut() {
+            unit_refs.push(Rc::get_mut(unit).unwrap());
+        }
+        // A file that can't be read leaves the slot as it was.
+        let decoded = state_file::read_file(path, &mut unit_refs)?;
+        self.valid = true;
+        self.level = decoded.header.level;
+        self.missing = decoded.missing;
+        Ok(decoded.header)
+    }
+}
+
+pub struct Session {
+    addresses: Addresses,
+    bindings: Bindings,
+    slots: Vec<Slot>,
+    active_slot: usize,
+    scheduler: LoadScheduler,
+    clock: StageClock,
+    loading_slot: usize,
+    // A load asked for while paused, to do once the game's unpaused.
+    waiting_load: Option<usize>,
+    // Whether loads are allowed at all: the build is known, or trusted, or
+    // this is a dry run.
+    can_load: bool,
+    // Whether poll can mark the score to show the tool's running. Not
+    // during dry runs of read-only sessions.
+    writable: bool,
+    build_id: u32,
+    slot_dir: Option<PathBuf>,
+    messages: Vec<String>,
+}
+
+impl Session {
+    pub fn new(options: &UnitOptions, addresses: &Addresses, bindings: Bindings, build_id: u32) -> Session {
+        Session {
+            addresses: *addresses,
+            bindings,
+            slots: (0..SLOT_COUNT).map(|_| Slot::new(options, addresses)).collect(),
+            active_slot: 0,
+            scheduler: LoadScheduler::new(),
+            clock: StageClock::new(),
+            loading_slot: 0,
+            waiting_load: None,
+            can_load: true,
+            writable: true,
+            build_id,
+            slot_dir: None,
+            messages: Vec::new(),
+        }
+    }
+
+    // Only lets loads through if `can_load`, and only marks the score if
+    // `writable`.
+    pub fn set_access(&mut self, writable: bool, can_load: bool) {
+        self.writable = writable;
+        self.can_load = can_load;
+    }
+
+    // Writes each slot to a file in `dir` when it's saved.
+    pub fn set_slot_dir(&mut self, dir: &Path) {
+        self.slot_dir = Some(dir.to_path_buf());
+    }
+
+    pub fn slot_path(&self, idx: usize) -> Option<PathBuf> {
+        self.slot_dir.as_ref().map(|dir| dir.join(format!("savestate_slot{}.ovst", idx + 1)))
+    }
+
+    pub fn slot(&self, idx: usize) -> &Slot {
+        &self.slots[idx]
+    }
+
+    pub fn active_slot(&self) -> usize {
+        self.active_slot
+    }
+
+    // Whether a load is still being applied over the next frames.
+    pub fn is_loading(&self) -> bool {
+        !self.scheduler.is_idle()
+    }
+
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    // Picks up the savestates from last session, if there are any.
+    pub fn read_slot_files(&mut self) {
+        for idx in 0..SLOT_COUNT {
+            let path = match self.slot_path(idx) {
+                Some(path) if path.exists() => path,
+                _ => continue,
+            };
+            let slot = &mut self.slots[idx];
+            match slot.read_file(&path) {
+                Ok(header) => {
+                    if header.build_id != self.build_id {
+                        slot.valid = false;
+                        self.messages.push(format!("Savestate in \"{}\" is for a different game build. Ignoring it.", path.display()));
+                    } else {
+                        self.messages.push(format!("Loaded slot {} from \"{}\".", idx + 1, path.display()));
+                        if !slot.missing.is_empty() {
+                            self.messages.push(format!("It was made without {}, so loading it leaves those alone.", slot.missing.join(", ")));
+                        }
+                    }
+                }
+                Err(err) => self.messages.push(format!("Error: {}", err)),
+            }
+        }
+    }
+
+    // One pass over the game. Loads write through `load_handle`, which is
+    // `handle` unless this is a dry run.
+    pub fn poll(&mut self, handle: &dyn GameMemory, load_handle: &dyn GameMemory) -> Result<(), Error> {
+        let addresses = self.addresses;
+        if self.writable {
+            let mut score = handle.read_u32(addresses.score)?;
+            score = score - (score % 10) + 1;
+            handle.write_u32(addresses.score, score)?;
+        }
+        let buttons = handle.read_u32(addresses.buttons)?;
+        let frame = handle.read_u32(addresses.frame_counter)?;
+        let actions = self.bindings.update(buttons, frame);
+
+        let level = handle.read_u32(addresses.level)?;
+
+        let game_state = GameState::read(handle, &addresses)?;
+        let game_state = self.clock.check(handle, &addresses, game_state)?;
+
+        let next_slot = actions.contains(&Action::NextSlot);
+        let prev_slot = actions.contains(&Action::PrevSlot);
+        if next_slot != prev_slot {
+            if next_slot {
+                self.active_slot = (self.active_slot + 1) % SLOT_COUNT;
+            } else {
+                self.active_slot = (self.active_slot + SLOT_COUNT - 1) % SLOT_COUNT;
+            }
+            let slot = &self.slots[self.active_slot];
+            if slot.valid {
+                self.messages.push(format!("Slot {} selected (level {}).", self.active_slot + 1, slot.level));
+            } else {
+                self.messages.push(format!("Slot {} selected (empty).", self.active_slot + 1));
+            }
+        }
+
+        if actions.contains(&Action::Save) {
+            if let Some(reason) = game_state.save_blocker() {
+                self.messages.push(format!("Cannot save state: {}.", reason));
+            } else {
+                let path = self.slot_path(self.active_slot);
+                let slot = &mut self.slots[self.active_slot];
+                let errors = slot.save(handle, level);
+                self.messages.extend(errors.iter().map(|err| format!("Error: {}", err)));
+                if slot.valid {
+                    self.messages.push(format!("Saving state to slot {}", self.active_slot + 1));
+                    if let Some(path) = path {
+                        let header = StateHeader::read(handle, &addresses, self.build_id)?;
+                        slot.write_file(&path, &header)?;
+                    }
+                } else {
+                    self.messages.push(format!("Could not save state. Slot {} not valid.", self.active_slot + 1));
+                }
+            }
+        }
+
+        let mut load_slot = None;
+        if actions.contains(&Action::Load) {
+            let slot = &self.slots[self.active_slot];
+            if !self.can_load {
+                self.messages.push("Cannot load state: game build not recognized.".to_string());
+            } else if !slot.valid {
+                self.messages.push(format!("Error: savestate in slot {} not valid", self.active_slot + 1));
+            } else if level != slot.level {
+                self.messages.push("Error: not the same stage as savestate".to_string());
+            } else if game_state.load_waits() {
+                self.messages.push(format!("Loading slot {} once the game is unpaused.", self.active_slot + 1));
+                self.waiting_load = Some(self.active_slot);
+            } else if let Some(reason) = game_state.load_blocker() {
+                self.messages.push(format!("Cannot load state: {}.", reason));
+            } else {
+                load_slot = Some(self.active_slot);
+            }
+        }
+        if let Some(slot_idx) = self.waiting_load {
+            if !game_state.in_stage() {
+                let reason = game_state.load_blocker().unwrap_or("left the stage");
+                self.messages.push(format!("Not loading slot {}: {}.", slot_idx + 1, reason));
+                self.waiting_load = None;
+            } else if game_state.load_blocker().is_none() {
+                load_slot = Some(slot_idx);
+                self.waiting_load = None;
+            }
+        }
+
+        if let Some(slot_idx) = load_slot {
+            self.messages.push(format!("Loading state from slot {}", slot_idx + 1));
+            self.loading_slot = slot_idx;
+            for err in self.scheduler.start(load_handle, &self.slots[slot_idx].load_refs(), frame) {
+                self.messages.push(format!("Error: {}", err));
+            }
+        }
+
+        // Units that need loading again on later frames.
+        if !self.scheduler.is_idle() {
+            let slot = &self.slots[self.loading_slot];
+            if level != slot.level || !game_state.in_stage() {
+                self.scheduler.cancel();
+            } else {
+                for err in self.scheduler.update(load_handle, &slot.load_refs(), frame) {
+                    self.messages.push(format!("Error: {}", err));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
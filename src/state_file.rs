@@ -0,0 +1,255 @@
+// Savestate file format
+//
+// Everything is little-endian.
+//
+// Header:
+//   0x00  [u8; 4]  magic, "OVST"
+//   0x04  u16      format version (currently 1)
+//   0x06  u32      game build id (TimeDateStamp from sonic2app.exe's PE
+//                  header; see BuildInfo::state_id)
+//   0x0a  u32      level id (from the level address)
+//   0x0e  u8       character id of player 1
+//   0x0f  u16      number of chunks
+//
+// Followed by one chunk per SaveStateUnit:
+//   u8       length of the unit name
+//   [u8]     unit name (ASCII)
+//   u32      length of the chunk data
+//   u32      CRC-32 (IEEE) of the chunk data
+//   [u8]     chunk data, as written by SaveStateUnit::write_state
+//
+// Chunks can come in any order, and there can't be two with the same name.
+// Every unit being loaded needs a chunk, except optional ones (see
+// SaveStateUnit::optional): the first files only had CharacterUnit (or
+// AllPlayersUnit), CameraUnit, TimeUnit and GravityUnit, and every unit
+// added since is optional. Chunks no unit asks for are ignored.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{GameMemory, SaveStateUnit};
+use crate::addresses::Addresses;
+use crate::error::{Error, ResultExt};
+use crate::sa2_structures::Character;
+
+pub const MAGIC: [u8; 4] = *b"OVST";
+pub const FORMAT_VERSION: u16 = 1;
+
+// Reads serialized savestate data back out of a byte slice.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::InvalidSavestate { reason: "unexpected end of data" });
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        let mut buf = [0; 2];
+        self.read_bytes(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0; 4];
+        self.read_bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+// CRC-32 (IEEE 802.3), bit at a time. Savestates are small enough that a
+// table isn't worth it.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// What decode read out of a file.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct DecodedState {
+    pub header: StateHeader,
+    // Optional units the file has no chunk for. They weren't changed.
+    pub missing: Vec<&'static str>,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct StateHeader {
+    pub build_id: u32,
+    pub level: u32,
+    pub character: u8,
+}
+
+impl StateHeader {
+    // Reads the header values for whatever the game is doing right now.
+    pub fn read(handle: &dyn GameMemory, addresses: &Addresses, build_id: u32) -> Result<StateHeader, Error> {
+        Ok(StateHeader {
+            build_id,
+            level: handle.read_u32(addresses.level).context("level")?,
+            character: read_character_id(handle, addresses).context("character")?,
+        })
+    }
+}
+
+// Character id of player 1, from their physics struct.
+pub fn read_character_id(handle: &dyn GameMemory, addresses: &Addresses) -> Result<u8, Error> {
+    let character = handle.read_ptr(addresses.character_tasks)?;
+    if character == 0 {
+        return Err(Error::NullPointer { address: addresses.character_tasks });
+    }
+    Character::read_id(handle, character)
+}
+
+pub fn encode(header: &StateHeader, units: &[&dyn SaveStateUnit]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&header.build_id.to_le_bytes());
+    out.extend_from_slice(&header.level.to_le_bytes());
+    out.push(header.character);
+    out.extend_from_slice(&(units.len() as u16).to_le_bytes());
+
+    for unit in units {
+        let name = unit.name().as_bytes();
+        let mut data = Vec::new();
+        unit.write_state(&mut data);
+
+        out.push(name.len() as u8);
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32(&data).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+    out
+}
+
+// Either every unit is loaded from the file or, if anything in it is bad,
+// none of them are changed.
+pub fn decode(data: &[u8], units: &mut [&mut dyn SaveStateUnit]) -> Result<DecodedState, Error> {
+    let mut reader = StateReader::new(data);
+    if reader.take(4)? != MAGIC {
+        return Err(Error::InvalidSavestate { reason: "not a savestate file" });
+    }
+    let version = reader.read_u16()?;
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion { version });
+    }
+    let header = StateHeader {
+        build_id: reader.read_u32()?,
+        level: reader.read_u32()?,
+        character: reader.read_u8()?,
+    };
+
+    let chunk_count = reader.read_u16()?;
+    let mut chunks = Vec::with_capacity(chunk_count as usize);
+    for _ in 0..chunk_count {
+        let name_len = reader.read_u8()? as usize;
+        let name = reader.take(name_len)?;
+        let data_len = reader.read_u32()? as usize;
+        let checksum = reader.read_u32()?;
+        let chunk_data = reader.take(data_len)?;
+        if crc32(chunk_data) != checksum {
+            return Err(Error::InvalidSavestate { reason: "chunk checksum mismatch" });
+        }
+        chunks.push((name, chunk_data));
+    }
+    if !reader.is_empty() {
+        return Err(Error::InvalidSavestate { reason: "trailing data after last chunk" });
+    }
+
+    let mut unit_data = Vec::with_capacity(units.len());
+    let mut missing = Vec::new();
+    for unit in units.iter() {
+        let name = unit.name();
+        let mut matching = chunks.iter().filter(|chunk| chunk.0 == name.as_bytes());
+        match (matching.next(), matching.next()) {
+            (Some(chunk), None) => unit_data.push(Some(chunk.1)),
+            (None, _) if unit.optional() => {
+                unit_data.push(None);
+                missing.push(name);
+            }
+            (None, _) => return Err(Error::InvalidSavestate { reason: "missing unit chunk" }).context(name),
+            (Some(_), Some(_)) => return Err(Error::InvalidSavestate { reason: "duplicate unit chunk" }).context(name),
+        }
+    }
+
+    // A chunk can still turn out bad once a unit reads it, so keep what
+    // every unit had and put it back if any of them fails.
+    let backups: Vec<Vec<u8>> = units.iter()
+        .map(|unit| {
+            let mut backup = Vec::new();
+            unit.write_state(&mut backup);
+            backup
+        })
+        .collect();
+    for (idx, chunk_data) in unit_data.into_iter().enumerate() {
+        let chunk_data = match chunk_data {
+            Some(chunk_data) => chunk_data,
+            None => continue,
+        };
+        if let Err(err) = read_chunk(&mut *units[idx], chunk_data) {
+            for (unit, backup) in units[..=idx].iter_mut().zip(backups.iter()) {
+                unit.read_state(&mut StateReader::new(backup))
+                    .expect("a unit can always read back its own state");
+            }
+            return Err(err);
+        }
+    }
+
+    Ok(DecodedState {
+        header,
+        missing,
+    })
+}
+
+fn read_chunk(unit: &mut dyn SaveStateUnit, chunk_data: &[u8]) -> Result<(), Error> {
+    let name = unit.name();
+    let mut chunk_reader = StateReader::new(chunk_data);
+    unit.read_state(&mut chunk_reader).context(name)?;
+    if !chunk_reader.is_empty() {
+        return Err(Error::InvalidSavestate { reason: "chunk longer than unit data" }).context(name);
+    }
+    Ok(())
+}
+
+pub fn write_file(path: &Path, header: &StateHeader, units: &[&dyn SaveStateUnit]) -> Result<(), Error> {
+    fs::write(path, encode(header, units))
+        .map_err(|e| Error::Io { operation: "writing savestate file", code: e.raw_os_error() })
+}
+
+pub fn read_file(path: &Path, units: &mut [&mut dyn SaveStateUnit]) -> Result<DecodedState, Error> {
+    let data = fs::read(path)
+        .map_err(|e| Error::Io { operation: "reading savestate file", code: e.raw_os_error() })?;
+    decode(&data, units)
+}
@@ -0,0 +1,383 @@
+// Recording every access the tool makes to the game, and playing it back
+// without the game.
+//
+// Recorder wraps a backend and writes a line to a trace file for every
+// read_data/write_data call, as it happens. Replay serves reads from a
+// trace and checks that the tool makes exactly the same calls, in the same
+// order, writing the same bytes. So a session recorded against the game
+// once can be replayed as a test.
+//
+// Trace files are text:
+//
+//   # comments start with '#'
+//   order little
+//   pointer 4
+//   r 0x01dea6e0 4 e0a6de01        # a read: address, length, bytes read
+//   r 0x00000000 4 error 299       # a read that failed, with the OS error
+//   w 0x0174b050 2a000000 4        # a write: address, bytes, bytes written
+//
+// The order and pointer lines come first and give the backend's byte_order
+// and pointer_size. A read that came up short has fewer bytes than its
+// length, and one that got nothing at all has "-" for its bytes. Failed
+// calls replay as Error::Access, whatever the original error was.
+
+use std::cell::{Cell, RefCell};
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::{parse_number, ByteOrder, GameMemory};
+use crate::error::{AccessKind, Error};
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum TraceEntry {
+    Read {
+        address: u64,
+        requested: usize,
+        // The bytes read, or the OS error if the read failed.
+        result: Result<Vec<u8>, Option<i32>>,
+    },
+    Write {
+        address: u64,
+        data: Vec<u8>,
+        // How many bytes were written, or the OS error.
+        result: Result<usize, Option<i32>>,
+    },
+}
+
+fn write_hex(line: &mut String, bytes: &[u8]) {
+    if bytes.is_empty() {
+        line.push('-');
+    }
+    for byte in bytes {
+        write!(line, "{:02x}", byte).unwrap();
+    }
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if text == "-" {
+        return Some(Vec::new());
+    }
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+fn write_error(line: &mut String, code: Option<i32>) {
+    line.push_str("error");
+    if let Some(code) = code {
+        write!(line, " {}", code).unwrap();
+    }
+}
+
+// The result at the end of an entry: `parse_ok`'s, or "error" with an
+// optional OS error code.
+fn parse_result<T>(fields: &[&str], parse_ok: impl FnOnce(&str) -> Result<T, &'static str>) -> Result<Result<T, Option<i32>>, &'static str> {
+    match *fields {
+        ["error"] => Ok(Err(None)),
+        ["error", code] => code.parse().map(|code| Err(Some(code))).map_err(|_| "error code is not a number"),
+        [value] => parse_ok(value).map(Ok),
+        [] => Err("expected a result"),
+        _ => Err("too many fields"),
+    }
+}
+
+impl TraceEntry {
+    pub fn to_line(&self) -> String {
+        let mut line = String::new();
+        match *self {
+            TraceEntry::Read { address, requested, ref result } => {
+                write!(line, "r {:#010x} {} ", address, requested).unwrap();
+                match *result {
+                    Ok(ref data) => write_hex(&mut line, data),
+                    Err(code) => write_error(&mut line, code),
+                }
+            }
+            TraceEntry::Write { address, ref data, ref result } => {
+                write!(line, "w {:#010x} ", address).unwrap();
+                write_hex(&mut line, data);
+                line.push(' ');
+                match *result {
+                    Ok(written) => write!(line, "{}", written).unwrap(),
+                    Err(code) => write_error(&mut line, code),
+                }
+            }
+        }
+        line
+    }
+
+    pub fn parse(line: &str) -> Result<TraceEntry, &'static str> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            return Err("expected \"r address length bytes\" or \"w address bytes result\"");
+        }
+        let address = parse_number(fields[1]).ok_or("address is not a number")?;
+        match fields[0] {
+            "r" => {
+                let requested = fields[2].parse().map_err(|_| "length is not a number")?;
+                let result = parse_result(&fields[3..], |text| parse_hex(text).ok_or("bytes are not hex"))?;
+                if let Ok(ref data) = result {
+                    if data.len() > requested {
+                        return Err("more bytes read than asked for");
+                    }
+                }
+                Ok(TraceEntry::Read { address, requested, result })
+            }
+            "w" => {
+                let data = parse_hex(fields[2]).ok_or("bytes are not hex")?;
+                let result = parse_result(&fields[3..], |text| text.parse().map_err(|_| "length is not a number"))?;
+                Ok(TraceEntry::Write { address, data, result })
+            }
+            _ => Err("expected \"r\" or \"w\""),
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Trace {
+    pub byte_order: ByteOrder,
+    pub pointer_size: usize,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn parse(text: &str) -> Result<Trace, Error> {
+        let mut byte_order = None;
+        let mut pointer_size = None;
+        let mut entries = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidConfig { line: idx + 1, reason };
+
+            if let Some(order) = line.strip_prefix("order ") {
+                byte_order = match order.trim() {
+                    "little" => Some(ByteOrder::Little),
+                    "big" => Some(ByteOrder::Big),
+                    _ => return Err(invalid("byte order should be \"little\" or \"big\"")),
+                };
+            } else if let Some(size) = line.strip_prefix("pointer ") {
+                pointer_size = match size.trim() {
+                    "4" => Some(4),
+                    "8" => Some(8),
+                    _ => return Err(invalid("pointer size should be 4 or 8")),
+                };
+            } else if byte_order.is_none() || pointer_size.is_none() {
+                return Err(invalid("expected the byte order and pointer size first"));
+            } else {
+                entries.push(TraceEntry::parse(line).map_err(invalid)?);
+            }
+        }
+        Ok(Trace {
+            byte_order: byte_order.unwrap_or(ByteOrder::Little),
+            pointer_size: pointer_size.unwrap_or(4),
+            entries,
+        })
+    }
+
+    pub fn read_file(path: &Path) -> Result<Trace, Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Io { operation: "reading trace file", code: e.raw_os_error() })?;
+        Trace::parse(&text)
+    }
+}
+
+// Passes everything through to `inner`, writing each call to `out`.
+pub struct Recorder<M> {
+    inner: M,
+    out: RefCell<Box<dyn Write>>,
+}
+
+impl<M: GameMemory> Recorder<M> {
+    pub fn new(inner: M, mut out: Box<dyn Write>) -> Result<Recorder<M>, Error> {
+        let order = match inner.byte_order() {
+            ByteOrder::Little => "little",
+            ByteOrder::Big => "big",
+        };
+        writeln!(out, "order {}\npointer {}", order, inner.pointer_size())
+            .map_err(|e| Error::Io { operation: "writing trace file", code: e.raw_os_error() })?;
+        Ok(Recorder {
+            inner,
+            out: RefCell::new(out),
+        })
+    }
+
+    // Records to a new file at `path`. Lines go out unbuffered, so the
+    // trace is complete up to the last call however the session ends.
+    pub fn create(inner: M, path: &Path) -> Result<Recorder<M>, Error> {
+        let file = File::create(path)
+            .map_err(|e| Error::Io { operation: "creating trace file", code: e.raw_os_error() })?;
+        Recorder::new(inner, Box::new(file))
+    }
+
+    fn record(&self, entry: TraceEntry) -> Result<(), Error> {
+        let mut line = entry.to_line();
+        line.push('\n');
+        self.out.borrow_mut().write_all(line.as_bytes())
+            .map_err(|e| Error::Io { operation: "writing trace file", code: e.raw_os_error() })
+    }
+}
+
+impl<M: GameMemory> GameMemory for Recorder<M> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let result = self.inner.read_data(address, buf);
+        self.record(TraceEntry::Read {
+            address,
+            requested: buf.len(),
+            result: match result {
+                Ok(read) => Ok(buf[..read].to_vec()),
+                Err(ref err) => Err(err.os_code()),
+            },
+        })?;
+        result
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        let result = self.inner.write_data(address, buf);
+        self.record(TraceEntry::Write {
+            address,
+            data: buf.to_vec(),
+            result: match result {
+                Ok(written) => Ok(written),
+                Err(ref err) => Err(err.os_code()),
+            },
+        })?;
+        result
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        self.inner.byte_order()
+    }
+
+    fn pointer_size(&self) -> usize {
+        self.inner.pointer_size()
+    }
+}
+
+// Plays back a trace. Every call has to be the next one in the trace;
+// the first one that isn't fails with TraceMismatch, and so does every
+// call after it. Calls after the end of the trace fail too, but that's not
+// counted as a mismatch.
+pub struct Replay {
+    trace: Trace,
+    // The index of the next entry.
+    next: Cell<usize>,
+    mismatch: RefCell<Option<Error>>,
+}
+
+impl Replay {
+    pub fn new(trace: Trace) -> Replay {
+        Replay {
+            trace,
+            next: Cell::new(0),
+            mismatch: RefCell::new(None),
+        }
+    }
+
+    // Moves on to the next entry, if `check` says it matches the call
+    // being made.
+    fn next_entry<T>(&self, check: impl FnOnce(&TraceEntry) -> Result<T, &'static str>) -> Result<T, Error> {
+        if let Some(ref err) = *self.mismatch.borrow() {
+            return Err(err.clone());
+        }
+        let idx = self.next.get();
+        let entry = match self.trace.entries.get(idx) {
+            Some(entry) => entry,
+            // Not a mismatch: recording can stop at any point.
+            None => return Err(Error::TraceMismatch { entry: idx + 1, reason: "the trace has ended" }),
+        };
+        match check(entry) {
+            Ok(value) => {
+                self.next.set(idx + 1);
+                Ok(value)
+            }
+            Err(reason) => {
+                let err = Error::TraceMismatch { entry: idx + 1, reason };
+                *self.mismatch.borrow_mut() = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+
+    // Whether there's nothing more to play back, because the trace has
+    // been used up or didn't match.
+    pub fn is_over(&self) -> bool {
+        self.mismatch.borrow().is_some() || self.next.get() == self.trace.entries.len()
+    }
+
+    // Checks that the whole trace was played back without a mismatch.
+    pub fn finish(&self) -> Result<(), Error> {
+        if let Some(ref err) = *self.mismatch.borrow() {
+            return Err(err.clone());
+        }
+        let idx = self.next.get();
+        if idx < self.trace.entries.len() {
+            return Err(Error::TraceMismatch { entry: idx + 1, reason: "calls were left over" });
+        }
+        Ok(())
+    }
+}
+
+impl GameMemory for Replay {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let result = self.next_entry(|entry| match *entry {
+            TraceEntry::Read { address: traced, requested, ref result } => {
+                if traced != address {
+                    Err("read from a different address")
+                } else if requested != buf.len() {
+                    Err("read a different length")
+                } else {
+                    Ok(result.clone())
+                }
+            }
+            TraceEntry::Write { .. } => Err("expected a write"),
+        })?;
+        match result {
+            Ok(data) => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Err(code) => Err(Error::Access {
+                kind: AccessKind::Read,
+                address,
+                requested: buf.len(),
+                code,
+            }),
+        }
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        let result = self.next_entry(|entry| match *entry {
+            TraceEntry::Write { address: traced, ref data, result } => {
+                if traced != address {
+                    Err("wrote to a different address")
+                } else if data[..] != *buf {
+                    Err("wrote different bytes")
+                } else {
+                    Ok(result)
+                }
+            }
+            TraceEntry::Read { .. } => Err("expected a read"),
+        })?;
+        result.map_err(|code| Error::Access {
+            kind: AccessKind::Write,
+            address,
+            requested: buf.len(),
+            code,
+        })
+    }
+
+    fn byte_order(&self) -> ByteOrder {
+        self.trace.byte_order
+    }
+
+    fn pointer_size(&self) -> usize {
+        self.trace.pointer_size
+    }
+}
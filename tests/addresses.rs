@@ -0,0 +1,131 @@
+use onvars_tool::addresses::{AddressDatabase, Addresses, DEFAULT_SECTION};
+use onvars_tool::build::BuildInfo;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::{CameraUnit, GravityUnit, LevelCollisionUnit};
+use onvars_tool::state_file::StateReader;
+use onvars_tool::SaveStateUnit;
+
+mod common;
+use common::*;
+
+#[test]
+fn built_in_addresses_match_the_old_constants() {
+    let addresses = Addresses::default();
+    assert_eq!(addresses.character_tasks, CHARACTER_PTR);
+    assert_eq!(addresses.camera, CAMERA);
+    assert_eq!(addresses.past_positions_ptr, PAST_POSITIONS_PTR);
+    assert_eq!(addresses.past_rotations_ptr, PAST_ROTATIONS_PTR);
+    assert_eq!(addresses.past_positions_idx, PAST_POSITIONS_IDX);
+    assert_eq!(addresses.camera_cons_past_positions, CAMERA_CONS_PAST_POSITIONS);
+    assert_eq!(addresses.camera_cons_past_positions_idx, CAMERA_CONS_PAST_POSITIONS_IDX);
+    assert_eq!(addresses.time, TIME);
+    assert_eq!(addresses.gravity, GRAVITY);
+    assert_eq!(addresses.rings, RINGS);
+    assert_eq!(addresses.lives, LIVES);
+    assert_eq!(addresses.score, SCORE);
+    assert_eq!(addresses.rng_seed, RNG_SEED);
+    assert_eq!(addresses.dyncol_list, DYNCOL_LIST);
+    assert_eq!(addresses.dyncol_count, DYNCOL_COUNT);
+    assert_eq!(addresses.object_lists, OBJECT_LISTS);
+    assert_eq!(addresses.level, LEVEL);
+    assert_eq!(addresses.game_state, GAME_STATE);
+}
+
+#[test]
+fn sections_inherit_from_default() {
+    let database = AddressDatabase::parse("\
+        [patched]\n\
+        header_crc = 0x1234abcd   # some other build\n\
+        gravity = 0x02000000\n\
+    ").unwrap();
+    assert_eq!(database.build_name(0x1234abcd), Some("patched"));
+    assert!(database.addresses("patched").is_err());
+
+    let mut merged = AddressDatabase::default();
+    merged.merge(database);
+    assert_eq!(merged.build_name(0x1234abcd), Some("patched"));
+    assert_eq!(merged.build_name(0x1234abce), None);
+
+    let addresses = merged.addresses("patched").unwrap();
+    assert_eq!(addresses.gravity, 0x0200_0000);
+    assert_eq!(addresses.time, TIME);
+    assert_eq!(merged.addresses(DEFAULT_SECTION).unwrap(), Addresses::default());
+}
+
+#[test]
+fn override_replaces_single_symbols() {
+    let mut database = AddressDatabase::default();
+    database.merge(AddressDatabase::parse("[default]\nscore = 100\n").unwrap());
+    let addresses = database.addresses(DEFAULT_SECTION).unwrap();
+    assert_eq!(addresses.score, 100);
+    assert_eq!(addresses.rings, RINGS);
+}
+
+#[test]
+fn units_use_the_addresses_they_are_given() {
+    let mem = FakeMemory::new();
+    let mut database = AddressDatabase::default();
+    database.merge(AddressDatabase::parse("[moved]\ngravity = 0x02000000\n").unwrap());
+    let addresses = database.addresses("moved").unwrap();
+
+    mem.seed(0x0200_0000, &pattern(1, 0xc));
+    mem.seed(GRAVITY, &pattern(2, 0xc));
+    let mut unit = GravityUnit::new(&addresses);
+    unit.save(&mem).unwrap();
+    mem.seed(0x0200_0000, &pattern(3, 0xc));
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(0x0200_0000, 0xc), pattern(1, 0xc));
+    assert_eq!(mem.bytes(GRAVITY, 0xc), pattern(2, 0xc));
+}
+
+#[test]
+fn struct_sizes_come_from_the_database() {
+    let addresses = Addresses::default();
+    assert_eq!(addresses.camera_size, 0x2518);
+    assert_eq!(addresses.dyncol_max, 0x400);
+
+    let mut database = AddressDatabase::default();
+    database.merge(AddressDatabase::parse("[resized]\ncamera_size = 0x100\ndyncol_max = 2\n").unwrap());
+    let addresses = database.addresses("resized").unwrap();
+    assert_eq!(CameraUnit::new(&addresses).regions()[0].len, 0x100);
+    assert_eq!(LevelCollisionUnit::new(&addresses).regions()[0].len, 2 * 0xc);
+
+    let data = 3u16.to_le_bytes();
+    let mut unit = LevelCollisionUnit::new(&addresses);
+    assert!(unit.read_state(&mut StateReader::new(&data)).is_err());
+}
+
+#[test]
+fn missing_symbols_are_an_error() {
+    let database = AddressDatabase::parse("[default]\ntime = 1\n").unwrap();
+    assert_eq!(database.addresses(DEFAULT_SECTION).unwrap_err(), Error::MissingAddress { symbol: "character_tasks" });
+}
+
+#[test]
+fn bad_lines_are_rejected() {
+    let invalid = |line, reason| Err(Error::InvalidConfig { line, reason });
+    let parse = |text| AddressDatabase::parse(text).map(|_| ());
+    assert_eq!(parse("time = 1"), invalid(1, "expected a [section] first"));
+    assert_eq!(parse("[a]\ntime"), invalid(2, "expected \"symbol = address\""));
+    assert_eq!(parse("[a]\ntyme = 1"), invalid(2, "unknown symbol"));
+    assert_eq!(parse("[a]\ntime = 0xzz"), invalid(2, "address is not a number"));
+    assert_eq!(parse("[a]\ntime = 1\ntime = 2"), invalid(3, "duplicate symbol"));
+    assert_eq!(parse("[a]\n[a]"), invalid(2, "duplicate section"));
+    assert_eq!(parse("[a"), invalid(1, "expected \"[section]\""));
+    assert_eq!(parse("[default]\nheader_crc = 1"), invalid(2, "the default section can't have a header_crc"));
+    assert_eq!(parse("[a]\nheader_crc = 0x100000000"), invalid(2, "header_crc is more than 32 bits"));
+}
+
+#[test]
+fn detected_build_is_looked_up_by_crc() {
+    let mem = FakeMemory::new();
+    seed_pe_header(&mem, 1);
+    let build = BuildInfo::detect(&mem).unwrap();
+
+    let mut database = AddressDatabase::default();
+    assert_eq!(database.build_name(build.header_crc), None);
+    let text = format!("[test]\nheader_crc = {:#x}\n", build.header_crc);
+    database.merge(AddressDatabase::parse(&text).unwrap());
+    assert_eq!(database.build_name(build.header_crc), Some("test"));
+}
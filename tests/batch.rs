@@ -0,0 +1,217 @@
+use std::cell::Cell;
+
+use onvars_tool::addresses::Addresses;
+use onvars_tool::batch::{coalesce_pages, coalesce_writes, unit_regions, Batch, MemoryRange};
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::load_scheduler::LoadScheduler;
+use onvars_tool::sa2_units::{CameraUnit, CharacterUnit, CounterToggles, CountersUnit, GravityUnit, TimeUnit};
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+// Counts the transfers that reach the game.
+struct Counting<'a> {
+    inner: &'a FakeMemory,
+    reads: Cell<usize>,
+    writes: Cell<usize>,
+}
+
+impl<'a> Counting<'a> {
+    fn new(inner: &'a FakeMemory) -> Counting<'a> {
+        Counting {
+            inner,
+            reads: Cell::new(0),
+            writes: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> GameMemory for Counting<'a> {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.read_data(address, buf)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        self.writes.set(self.writes.get() + 1);
+        self.inner.write_data(address, buf)
+    }
+}
+
+fn state(unit: &dyn SaveStateUnit) -> Vec<u8> {
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    data
+}
+
+#[test]
+fn pages_are_merged_into_runs() {
+    let ranges = [
+        MemoryRange::new(0x2ff0, 0x20),
+        MemoryRange::new(0x1004, 4),
+        MemoryRange::new(0x1ff0, 1),
+        MemoryRange::new(0x6000, 0),
+        MemoryRange::new(0x8000, 0x1000),
+    ];
+    assert_eq!(coalesce_pages(&ranges), vec![
+        MemoryRange::new(0x1000, 0x3000),
+        MemoryRange::new(0x8000, 0x1000),
+    ]);
+}
+
+#[test]
+fn writes_merge_when_they_touch() {
+    let writes = vec![
+        (0x1000, vec![1, 1, 1, 1]),
+        (0x1004, vec![2, 2]),
+        (0x1002, vec![3]),
+        (0x1008, vec![4]),
+    ];
+    assert_eq!(coalesce_writes(&writes), vec![
+        (0x1000, vec![1, 1, 3, 1, 2, 2]),
+        (0x1008, vec![4]),
+    ]);
+}
+
+#[test]
+fn camera_saves_in_fewer_reads() {
+    let mem = FakeMemory::new();
+    seed_camera(&mem, 1);
+
+    let direct = Counting::new(&mem);
+    let mut expected = CameraUnit::new(&Addresses::default());
+    expected.save(&direct).unwrap();
+
+    let counting = Counting::new(&mem);
+    let batch = Batch::new(&counting);
+    let mut unit = CameraUnit::new(&Addresses::default());
+    batch.prefetch(&unit_regions(&[&unit]));
+    unit.save(&batch).unwrap();
+
+    assert_eq!(state(&unit), state(&expected));
+    assert_eq!(direct.reads.get(), 8);
+    // The camera, the two pointers, past_positions_idx, the camera
+    // constraint table and its index are all fetched up front in four
+    // runs, and the tables behind the pointers are a page each.
+    assert_eq!(counting.reads.get(), 6);
+}
+
+#[test]
+fn character_pointers_come_from_cached_pages() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 4, 1);
+
+    let direct = Counting::new(&mem);
+    let mut expected = CharacterUnit::new(&Addresses::default());
+    expected.save(&direct).unwrap();
+
+    let counting = Counting::new(&mem);
+    let batch = Batch::new(&counting);
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&batch).unwrap();
+
+    assert_eq!(state(&unit), state(&expected));
+    assert!(counting.reads.get() < direct.reads.get(), "{} reads, {} without batching", counting.reads.get(), direct.reads.get());
+
+    // Saving again is free.
+    let reads = counting.reads.get();
+    unit.save(&batch).unwrap();
+    assert_eq!(counting.reads.get(), reads);
+}
+
+#[test]
+fn writes_wait_for_flush() {
+    let mem = FakeMemory::new();
+    mem.seed(TIME, &pattern(1, 3));
+    mem.seed(0x0174B020, &[0; 0x40]);
+    let counting = Counting::new(&mem);
+    let batch = Batch::new(&counting);
+
+    let mut counters = CountersUnit::new(&Addresses::default(), CounterToggles::all());
+    counters.save(&batch).unwrap();
+    batch.write_all(LIVES, &[9]).unwrap();
+    batch.write_u32(RINGS, 0x0002_0001).unwrap();
+    assert_eq!(counting.writes.get(), 0);
+    assert_eq!(mem.bytes(LIVES, 1), vec![0]);
+
+    // Reads see the writes, whether or not the page was cached first.
+    assert_eq!(batch.read_u8(LIVES).unwrap(), 9);
+    assert_eq!(batch.read_u32(RINGS).unwrap(), 0x0002_0001);
+    assert_eq!(batch.read_u8(TIME).unwrap(), pattern(1, 1)[0]);
+
+    batch.flush().unwrap();
+    // LIVES and RINGS are 4 bytes apart, so they're two writes.
+    assert_eq!(counting.writes.get(), 2);
+    assert_eq!(mem.bytes(LIVES, 1), vec![9]);
+    assert_eq!(mem.bytes(RINGS, 4), vec![1, 0, 2, 0]);
+}
+
+#[test]
+fn unreadable_pages_fail_like_the_backend() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1ffc, &[1, 2, 3, 4]);
+    let batch = Batch::new(&mem);
+    let mut buf = [0; 8];
+
+    // Partly mapped: the page fetch fails, and the read goes to the backend.
+    assert_eq!(batch.read_data(0x1ffc, &mut buf), mem.read_data(0x1ffc, &mut buf));
+    assert_eq!(batch.read_data(0x5000, &mut buf).unwrap_err(), Error::Access {
+        kind: AccessKind::Read,
+        address: 0x5000,
+        requested: 8,
+        code: None,
+    });
+
+    // Writes fail when they're made.
+    batch.write_all(0x5000, &buf).unwrap();
+    assert_eq!(batch.flush().unwrap_err(), Error::Access {
+        kind: AccessKind::Write,
+        address: 0x5000,
+        requested: 8,
+        code: None,
+    });
+}
+
+#[test]
+fn scheduled_loads_are_batched() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 4, 1);
+    seed_camera(&mem, 1);
+    mem.seed(TIME, &pattern(1, 3));
+    mem.seed(GRAVITY, &pattern(2, 0xc));
+    let mut regions = character_regions(4);
+    regions.extend(camera_regions());
+    regions.push(Region { address: TIME, len: 3 });
+    regions.push(Region { address: GRAVITY, len: 0xc });
+    let before = snapshot(&mem, &regions);
+
+    let addresses = Addresses::default();
+    let mut units: Vec<Box<dyn SaveStateUnit>> = vec![
+        Box::new(CharacterUnit::new(&addresses)),
+        Box::new(CameraUnit::new(&addresses)),
+        Box::new(TimeUnit::new(&addresses)),
+        Box::new(GravityUnit::new(&addresses)),
+    ];
+    for unit in units.iter_mut() {
+        unit.save(&mem).unwrap();
+    }
+    let refs: Vec<&dyn SaveStateUnit> = units.iter().map(|unit| &**unit).collect();
+
+    let direct = Counting::new(&mem);
+    for unit in refs.iter() {
+        unit.load(&direct).unwrap();
+    }
+
+    seed_character(&mem, 4, 50);
+    seed_camera(&mem, 50);
+    let counting = Counting::new(&mem);
+    let mut scheduler = LoadScheduler::new();
+    assert!(scheduler.start(&counting, &refs, 0).is_empty());
+    assert_eq!(snapshot(&mem, &regions), before);
+    // Pointers near each other are read together. None of these writes
+    // touch, so there are as many as before.
+    assert!(counting.reads.get() < direct.reads.get());
+    assert_eq!(counting.writes.get(), direct.writes.get());
+}
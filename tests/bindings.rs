@@ -0,0 +1,85 @@
+use onvars_tool::bindings::{Action, Binding, Bindings};
+use onvars_tool::error::Error;
+
+#[test]
+fn default_bindings_match_old_controls() {
+    let mut bindings = Bindings::default();
+    assert_eq!(bindings.update(0x1, 0), vec![Action::Save]);
+    assert_eq!(bindings.update(0x0, 1), vec![]);
+    assert_eq!(bindings.update(0x2, 2), vec![Action::Load]);
+    // Holding doesn't repeat.
+    assert_eq!(bindings.update(0x2, 3), vec![]);
+}
+
+#[test]
+fn chord_fires_when_last_button_goes_down() {
+    let mut bindings = Bindings::default();
+    assert_eq!(bindings.update(0x20, 0), vec![]);
+    assert_eq!(bindings.update(0x20 | 0x8, 1), vec![Action::NextSlot]);
+    assert_eq!(bindings.update(0x20, 2), vec![]);
+    assert_eq!(bindings.update(0x20 | 0x4, 3), vec![Action::PrevSlot]);
+}
+
+#[test]
+fn chord_shadows_its_parts() {
+    let mut bindings = Bindings::parse("save = Up\nnext_slot = R+Up\n").unwrap();
+    assert_eq!(bindings.update(0x20 | 0x8, 0), vec![Action::NextSlot]);
+    assert_eq!(bindings.update(0, 1), vec![]);
+    assert_eq!(bindings.update(0x8, 2), vec![Action::Save]);
+}
+
+#[test]
+fn hold_fires_once_after_frames() {
+    let mut bindings = Bindings::parse("load = Y+B hold 30").unwrap();
+    assert_eq!(bindings.update(0xa00, 100), vec![]);
+    assert_eq!(bindings.update(0xa00, 129), vec![]);
+    assert_eq!(bindings.update(0xa00, 130), vec![Action::Load]);
+    assert_eq!(bindings.update(0xa00, 200), vec![]);
+
+    // Letting go resets the timer.
+    assert_eq!(bindings.update(0x200, 201), vec![]);
+    assert_eq!(bindings.update(0xa00, 202), vec![]);
+    assert_eq!(bindings.update(0xa00, 232), vec![Action::Load]);
+}
+
+#[test]
+fn parses_names_bits_and_comments() {
+    let bindings = Bindings::parse("\
+        # practice layout\n\
+        save = start+z   # case doesn't matter\n\
+        load = Bit13\n\
+        load = L hold 5\n\
+    ").unwrap();
+    assert_eq!(bindings.bindings_for(Action::Save), vec![Binding { buttons: 0x1010, hold_frames: 0 }]);
+    assert_eq!(bindings.bindings_for(Action::Load), vec![
+        Binding { buttons: 0x2000, hold_frames: 0 },
+        Binding { buttons: 0x40, hold_frames: 5 },
+    ]);
+    assert_eq!(bindings.bindings_for(Action::NextSlot), vec![]);
+}
+
+#[test]
+fn describe_round_trips() {
+    let binding = Binding::parse("R+Up hold 10").unwrap();
+    assert_eq!(binding.describe(), "Up+R (hold 10 frames)");
+    assert_eq!(Binding::parse("Bit20").unwrap().describe(), "Bit20");
+}
+
+#[test]
+fn reports_bad_lines() {
+    let err = Bindings::parse("save = Left\nteleport = A\n").err().unwrap();
+    assert_eq!(err, Error::InvalidConfig { line: 2, reason: "unknown action" });
+
+    let err = Bindings::parse("save = Left+Q").err().unwrap();
+    assert_eq!(err, Error::InvalidConfig { line: 1, reason: "unknown button name" });
+
+    // Not ASCII, and the third byte is in the middle of the euro sign.
+    let err = Bindings::parse("save = a€").err().unwrap();
+    assert_eq!(err, Error::InvalidConfig { line: 1, reason: "unknown button name" });
+
+    let err = Bindings::parse("save = A hold").err().unwrap();
+    assert_eq!(err, Error::InvalidConfig { line: 1, reason: "missing frame count after \"hold\"" });
+
+    let err = Bindings::parse("save").err().unwrap();
+    assert_eq!(err, Error::InvalidConfig { line: 1, reason: "expected \"action = buttons\"" });
+}
@@ -0,0 +1,57 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::build::{BuildInfo, ReadOnly};
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::GravityUnit;
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+#[test]
+fn build_info_covers_the_headers() {
+    let mem = FakeMemory::new();
+    seed_pe_header(&mem, 0x1234_5678);
+    let first = BuildInfo::detect(&mem).unwrap();
+    assert_eq!(first.timestamp, 0x1234_5678);
+
+    // Anything in the headers changes the CRC, not just the timestamp.
+    mem.seed(0x400200, &[1]);
+    let second = BuildInfo::detect(&mem).unwrap();
+    assert_eq!(second.timestamp, first.timestamp);
+    assert_ne!(second.header_crc, first.header_crc);
+
+    // But nothing past them does.
+    mem.seed(0x400400, &[1]);
+    assert_eq!(BuildInfo::detect(&mem).unwrap(), second);
+}
+
+#[test]
+fn bad_header_size_is_an_error() {
+    let mem = FakeMemory::new();
+    seed_pe_header(&mem, 1);
+    mem.seed_u32(0x400154, 0x10_0000);
+    assert_eq!(BuildInfo::detect(&mem).unwrap_err(), Error::InvalidBuild { reason: "PE header size out of range" });
+    mem.seed_u32(0x400154, 0);
+    assert!(BuildInfo::detect(&mem).is_err());
+}
+
+#[test]
+fn missing_module_is_an_error() {
+    let mem = FakeMemory::new();
+    let err = BuildInfo::detect(&mem).unwrap_err();
+    assert_eq!(err.path(), &["build"]);
+}
+
+#[test]
+fn read_only_refuses_writes() {
+    let mem = FakeMemory::new();
+    mem.seed(GRAVITY, &pattern(1, 0xc));
+    let read_only = ReadOnly::new(mem);
+
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&read_only).unwrap();
+    let err = unit.load(&read_only).unwrap_err();
+    assert_eq!(err.root(), &Error::ReadOnly { address: GRAVITY, requested: 0xc });
+    assert_eq!(read_only.read_u8(GRAVITY).unwrap(), pattern(1, 1)[0]);
+}
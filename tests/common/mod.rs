@@ -0,0 +1,141 @@
+#![allow(dead_code)]
+
+use onvars_tool::fake_memory::FakeMemory;
+
+pub const CHARACTER_PTR: u64 = 0x01dea6e0;
+pub const TASK: u64 = 0x0300_0000;
+pub const ACTION: u64 = 0x0300_1000;
+pub const COLLISION: u64 = 0x0300_2000;
+pub const COLLISION_ELEMENTS: u64 = 0x0300_3000;
+pub const GLOBAL_METRICS: u64 = 0x0300_4000;
+pub const PHYSICS: u64 = 0x0300_5000;
+pub const LEVEL_COLLISION: u64 = 0x0300_6000;
+
+pub const CAMERA: u64 = 0x01dcff00;
+pub const PAST_POSITIONS_PTR: u64 = 0x01a5a234;
+pub const PAST_ROTATIONS_PTR: u64 = 0x01a5a238;
+pub const PAST_POSITIONS: u64 = 0x0310_0000;
+pub const PAST_ROTATIONS: u64 = 0x0310_1000;
+pub const PAST_POSITIONS_IDX: u64 = 0x01945910;
+pub const CAMERA_CONS_PAST_POSITIONS: u64 = 0x019f1740;
+pub const CAMERA_CONS_PAST_POSITIONS_IDX: u64 = 0x019f173c;
+
+pub const TIME: u64 = 0x0174AFDB;
+pub const GRAVITY: u64 = 0x01DE94A0;
+pub const LIVES: u64 = 0x0174B024;
+pub const RINGS: u64 = 0x0174B028;
+pub const SCORE: u64 = 0x0174B050;
+pub const RNG_SEED: u64 = 0x05CE05BC;
+
+pub const DYNCOL_LIST: u64 = 0x01a5a2dc;
+pub const DYNCOL_COUNT: u64 = 0x01de9484;
+pub const DYNCOL_MAX: usize = 0x400;
+pub const OBJECT_LISTS: u64 = 0x01a5a254;
+
+pub const LEVEL: u64 = 0x1934B70;
+pub const GAME_STATE: u64 = 0x1934BE0;
+
+// Deterministic junk so every byte of a region is distinguishable.
+pub fn pattern(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+pub struct Region {
+    pub address: u64,
+    pub len: usize,
+}
+
+pub fn snapshot(mem: &FakeMemory, regions: &[Region]) -> Vec<Vec<u8>> {
+    regions.iter().map(|r| mem.bytes(r.address, r.len)).collect()
+}
+
+pub fn phys_size(character_id: u8) -> usize {
+    match character_id {
+        0 | 1 | 8 | 0xc => 0x3a0,
+        4 | 5 | 0xf | 0x10 => 0x420,
+        6 | 7 | 0xd | 0xe => 0x454,
+        9 | 0xa => 0x1d8,
+        2 => 0x1f0,
+        3 => 0x1f8,
+        _ => 0x3a0,
+    }
+}
+
+pub fn character_regions(character_id: u8) -> Vec<Region> {
+    player_regions(0, character_id)
+}
+
+// Player N's structs live 0x10000 * N past player 1's.
+pub fn player_regions(player: usize, character_id: u8) -> Vec<Region> {
+    let offset = 0x10000 * player as u64;
+    vec![
+        Region { address: ACTION + offset, len: 0x30 },
+        Region { address: COLLISION + offset, len: 0xa8 },
+        Region { address: COLLISION_ELEMENTS + offset, len: 0x30 },
+        Region { address: GLOBAL_METRICS + offset, len: 0x40 },
+        Region { address: PHYSICS + offset, len: phys_size(character_id) },
+        Region { address: LEVEL_COLLISION + offset, len: 0x84 },
+    ]
+}
+
+// Fills every character struct with junk, then fixes up the pointers and
+// the character id so the structures can be followed.
+pub fn seed_character(mem: &FakeMemory, character_id: u8, seed: u32) {
+    seed_player(mem, 0, character_id, seed);
+}
+
+pub fn seed_player(mem: &FakeMemory, player: usize, character_id: u8, seed: u32) {
+    let offset = 0x10000 * player as u64;
+    for (i, region) in player_regions(player, character_id).iter().enumerate() {
+        mem.seed(region.address, &pattern(seed + i as u32, region.len));
+    }
+    mem.seed(TASK + offset, &pattern(seed + 100, 0x50));
+    mem.seed_u32(CHARACTER_PTR + 4 * player as u64, (TASK + offset) as u32);
+    mem.seed_u32(TASK + offset + 0x34, (ACTION + offset) as u32);
+    mem.seed_u32(TASK + offset + 0x38, (GLOBAL_METRICS + offset) as u32);
+    mem.seed_u32(TASK + offset + 0x40, (PHYSICS + offset) as u32);
+    mem.seed_u32(ACTION + offset + 0x2c, (COLLISION + offset) as u32);
+    mem.seed_u32(COLLISION + offset + 0xc, (COLLISION_ELEMENTS + offset) as u32);
+    mem.seed_u32(PHYSICS + offset + 0x90, (LEVEL_COLLISION + offset) as u32);
+    mem.seed(PHYSICS + offset + 0x1, &[character_id]);
+}
+
+pub fn clear_player(mem: &FakeMemory, player: usize) {
+    mem.seed_u32(CHARACTER_PTR + 4 * player as u64, 0);
+}
+
+pub fn camera_regions() -> Vec<Region> {
+    vec![
+        Region { address: CAMERA, len: 0x2518 },
+        Region { address: PAST_POSITIONS, len: 0xc00 },
+        Region { address: PAST_ROTATIONS, len: 0xc00 },
+        Region { address: PAST_POSITIONS_IDX, len: 1 },
+        Region { address: CAMERA_CONS_PAST_POSITIONS, len: 0xc00 },
+        Region { address: CAMERA_CONS_PAST_POSITIONS_IDX, len: 1 },
+    ]
+}
+
+pub fn seed_camera(mem: &FakeMemory, seed: u32) {
+    for (i, region) in camera_regions().iter().enumerate() {
+        mem.seed(region.address, &pattern(seed + i as u32, region.len));
+    }
+    mem.seed_u32(PAST_POSITIONS_PTR, PAST_POSITIONS as u32);
+    mem.seed_u32(PAST_ROTATIONS_PTR, PAST_ROTATIONS as u32);
+}
+
+// Just enough of a PE header for BuildInfo::detect, with `timestamp` as
+// what BuildInfo::state_id gives.
+pub fn seed_pe_header(mem: &FakeMemory, timestamp: u32) {
+    mem.seed_u32(0x40003c, 0x100);
+    mem.seed_u32(0x400108, timestamp);
+    // SizeOfHeaders
+    mem.seed_u32(0x400154, 0x400);
+}
@@ -0,0 +1,101 @@
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::state_file::StateReader;
+use onvars_tool::{GameMemory, SaveStateable};
+
+mod common;
+use common::*;
+
+const BASE: u64 = 0x0300_0000;
+const INNER: u64 = 0x0300_1000;
+
+#[derive(SaveStateable)]
+struct Inner(#[at(0x0)] [u8;0x8], #[at(0x10)] u32);
+
+#[derive(SaveStateable)]
+struct Outer {
+    #[at(0x0)]
+    head: [u8;0x4],
+    #[at(0x8, pointer)]
+    inner: Inner,
+    #[at(0xc)]
+    tail: u8,
+}
+
+impl Outer {
+    fn new() -> Outer {
+        Outer {
+            head: [0;0x4],
+            inner: Inner([0;0x8], 0),
+            tail: 0,
+        }
+    }
+}
+
+fn seed(mem: &FakeMemory, seed: u32) {
+    mem.seed(BASE, &pattern(seed, 0x10));
+    mem.seed_u32(BASE + 0x8, INNER as u32);
+    mem.seed(INNER, &pattern(seed + 1, 0x14));
+}
+
+fn regions() -> Vec<Region> {
+    vec![
+        Region { address: BASE, len: 0x10 },
+        Region { address: INNER, len: 0x14 },
+    ]
+}
+
+#[test]
+fn derived_struct_round_trip() {
+    let mem = FakeMemory::new();
+    seed(&mem, 1);
+    let before = snapshot(&mem, &regions());
+
+    let mut outer = Outer::new();
+    outer.save(&mem, BASE).unwrap();
+    seed(&mem, 50);
+    outer.load(&mem, BASE).unwrap();
+
+    // Only the annotated fields come back; the gaps keep the new values.
+    let after = snapshot(&mem, &regions());
+    assert_eq!(after[0][..0x4], before[0][..0x4]);
+    assert_eq!(after[0][0xc], before[0][0xc]);
+    assert_eq!(after[1][..0x8], before[1][..0x8]);
+    assert_eq!(after[1][0x10..], before[1][0x10..]);
+    assert_eq!(after[1][0x8..0x10], pattern(51, 0x14)[0x8..0x10]);
+}
+
+#[test]
+fn derived_struct_serializes_in_field_order() {
+    let mem = FakeMemory::new();
+    seed(&mem, 1);
+
+    let mut outer = Outer::new();
+    outer.save(&mem, BASE).unwrap();
+    let mut data = Vec::new();
+    outer.write_state(&mut data);
+
+    let mut expected = mem.bytes(BASE, 0x4);
+    expected.extend(mem.bytes(INNER, 0x8));
+    expected.extend(mem.bytes(INNER + 0x10, 0x4));
+    expected.extend(mem.bytes(BASE + 0xc, 0x1));
+    assert_eq!(data, expected);
+
+    let mut copy = Outer::new();
+    copy.read_state(&mut StateReader::new(&data)).unwrap();
+    let mut copied = Vec::new();
+    copy.write_state(&mut copied);
+    assert_eq!(copied, data);
+}
+
+#[test]
+fn derived_struct_null_pointer_reports_field() {
+    let mem = FakeMemory::new();
+    seed(&mem, 1);
+    mem.write_u32(BASE + 0x8, 0).unwrap();
+
+    let mut outer = Outer::new();
+    let err = outer.save(&mem, BASE).unwrap_err();
+    assert_eq!(err.root(), &Error::NullPointer { address: BASE + 0x8 });
+    assert_eq!(err.path(), &["inner"]);
+}
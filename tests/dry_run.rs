@@ -0,0 +1,125 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::dry_run::{diff, DryRun, LoggedWrite};
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::load_scheduler::LoadScheduler;
+use onvars_tool::sa2_units::{GravityUnit, LevelCollisionUnit};
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+#[test]
+fn writes_are_logged_instead_of_made() {
+    let mem = FakeMemory::new();
+    mem.seed(GRAVITY, &pattern(1, 0xc));
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    mem.seed(GRAVITY, &pattern(2, 0xc));
+
+    let dry_run = DryRun::new(&mem);
+    unit.load(&dry_run).unwrap();
+    assert_eq!(mem.bytes(GRAVITY, 0xc), pattern(2, 0xc));
+    assert_eq!(dry_run.take_writes(), vec![LoggedWrite {
+        address: GRAVITY,
+        old: pattern(2, 0xc),
+        new: pattern(1, 0xc),
+    }]);
+    assert!(dry_run.take_writes().is_empty());
+}
+
+#[test]
+fn reads_see_logged_writes() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1000, &[1, 2, 3, 4]);
+    let dry_run = DryRun::new(&mem);
+
+    dry_run.write_all(0x1001, &[5, 6]).unwrap();
+    dry_run.write_all(0x1002, &[7, 8]).unwrap();
+    assert_eq!(dry_run.read_u32(0x1000).unwrap(), 0x0807_0501);
+    assert_eq!(mem.bytes(0x1000, 4), vec![1, 2, 3, 4]);
+
+    // Old bytes are what the game would have had at that point.
+    let writes = dry_run.take_writes();
+    assert_eq!(writes[1].old, vec![6, 4]);
+    assert_eq!(dry_run.read_u32(0x1000).unwrap(), 0x0403_0201);
+}
+
+#[test]
+fn new_writes_stay_visible() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1000, &[1, 2, 3, 4]);
+    let dry_run = DryRun::new(&mem);
+
+    dry_run.write_all(0x1000, &[5]).unwrap();
+    assert_eq!(dry_run.new_writes().len(), 1);
+    dry_run.write_all(0x1001, &[6]).unwrap();
+    assert_eq!(dry_run.new_writes(), vec![LoggedWrite {
+        address: 0x1001,
+        old: vec![2],
+        new: vec![6],
+    }]);
+    assert!(dry_run.new_writes().is_empty());
+    assert_eq!(dry_run.read_u32(0x1000).unwrap(), 0x0403_0605);
+
+    dry_run.clear();
+    assert_eq!(dry_run.read_u32(0x1000).unwrap(), 0x0403_0201);
+    assert!(dry_run.new_writes().is_empty());
+}
+
+#[test]
+fn writes_to_unreadable_memory_fail() {
+    let mem = FakeMemory::new();
+    let dry_run = DryRun::new(&mem);
+    assert_eq!(dry_run.write_all(0x1000, &[1, 2]).unwrap_err(), Error::Access {
+        kind: AccessKind::Write,
+        address: 0x1000,
+        requested: 2,
+        code: None,
+    });
+    assert!(dry_run.take_writes().is_empty());
+}
+
+#[test]
+fn diff_shows_changed_rows() {
+    let mut new = vec![0; 0x24];
+    new[0x12] = 0xab;
+    let writes = [
+        LoggedWrite { address: 0x1000, old: vec![0; 0x24], new },
+        LoggedWrite { address: 0x2000, old: vec![1, 2], new: vec![1, 2] },
+    ];
+    assert_eq!(writes[0].changed(), 1);
+    assert_eq!(diff(&writes), "\
+0x00001000: 0x24 bytes, 0x1 changed
+  - 0x00001010  00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+  + 0x00001010  00 00 ab 00 00 00 00 00 00 00 00 00 00 00 00 00
+0x00002000: 0x2 bytes, unchanged
+");
+}
+
+#[test]
+fn collision_load_can_be_tried_out() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(DYNCOL_COUNT, 1);
+    mem.seed(DYNCOL_LIST, &[1, 0, 0, 0, 0, 0, 0x20, 0x03, 0, 0, 0x30, 0x03]);
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // The entry's flags change and another object's entry appears.
+    mem.seed(DYNCOL_LIST, &[2, 0, 0, 0, 0, 0, 0x20, 0x03, 0, 0, 0x30, 0x03]);
+    mem.seed(DYNCOL_LIST + 0xc, &[3, 0, 0, 0, 0, 0, 0x40, 0x03, 0, 0, 0x50, 0x03]);
+    mem.seed_u32(DYNCOL_COUNT, 2);
+    let before = mem.bytes(DYNCOL_LIST, 0x18);
+
+    let dry_run = DryRun::new(&mem);
+    let mut scheduler = LoadScheduler::new();
+    assert!(scheduler.start(&dry_run, &[&unit], 0).is_empty());
+    assert_eq!(mem.bytes(DYNCOL_LIST, 0x18), before);
+
+    let writes = dry_run.take_writes();
+    let list = writes.iter().find(|write| write.address == DYNCOL_LIST).unwrap();
+    assert_eq!(list.changed(), 1);
+    assert_eq!((list.old[0], list.new[0]), (2, 1));
+    // The list is only reordered, so the count stays as it is.
+    assert!(writes.iter().all(|write| write.address != DYNCOL_COUNT));
+}
@@ -0,0 +1,107 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::game_state::{GameState, StageClock, TIMER_STOPPED_FRAMES};
+
+mod common;
+use common::{GAME_STATE, TIME};
+
+const FRAME_COUNTER: u64 = 0x0174b03c;
+
+#[test]
+fn decodes_known_states() {
+    assert_eq!(GameState::from_raw(0x0), GameState::Inactive);
+    assert_eq!(GameState::from_raw(0x3), GameState::Loading);
+    assert_eq!(GameState::from_raw(0x7), GameState::Restarting);
+    assert_eq!(GameState::from_raw(0xc), GameState::Dying);
+    assert_eq!(GameState::from_raw(0x9), GameState::Exiting);
+    assert_eq!(GameState::from_raw(0x10), GameState::InLevel);
+    assert_eq!(GameState::from_raw(0x11), GameState::Paused);
+    assert_eq!(GameState::from_raw(0x1234), GameState::Unknown(0x1234));
+}
+
+#[test]
+fn reads_from_game_memory() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(GAME_STATE, 0x11);
+    assert_eq!(GameState::read(&mem, &Addresses::default()).unwrap(), GameState::Paused);
+}
+
+#[test]
+fn cutscenes_need_the_mode_address() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(GAME_STATE, 0x10);
+    mem.seed_u32(0x0100_0000, 7);
+    assert_eq!(GameState::read(&mem, &Addresses::default()).unwrap(), GameState::InLevel);
+
+    let addresses = Addresses {
+        game_mode: Some(0x0100_0000),
+        cutscene_mode: Some(7),
+        ..Addresses::default()
+    };
+    assert_eq!(GameState::read(&mem, &addresses).unwrap(), GameState::Cutscene);
+    mem.seed_u32(0x0100_0000, 6);
+    assert_eq!(GameState::read(&mem, &addresses).unwrap(), GameState::InLevel);
+
+    // Only a cutscene in the stage counts.
+    assert_eq!(GameState::from_raw_with_mode(0x11, 7, 7), GameState::Paused);
+}
+
+#[test]
+fn only_in_level_allows_loads() {
+    for raw in 0..0x20 {
+        let state = GameState::from_raw(raw);
+        assert_eq!(state.load_blocker().is_none(), state == GameState::InLevel, "{:?}", state);
+    }
+}
+
+#[test]
+fn saving_is_allowed_while_paused() {
+    assert_eq!(GameState::InLevel.save_blocker(), None);
+    assert_eq!(GameState::Paused.save_blocker(), None);
+    assert!(GameState::Restarting.save_blocker().is_some());
+    assert!(GameState::Dying.save_blocker().is_some());
+    assert!(GameState::Cutscene.save_blocker().is_some());
+    assert!(GameState::Exiting.save_blocker().is_some());
+    assert!(GameState::Unknown(0x40).save_blocker().is_some());
+}
+
+#[test]
+fn only_paused_loads_wait() {
+    assert!(GameState::Paused.load_waits());
+    assert!(GameState::Paused.in_stage());
+    for &state in [GameState::Loading, GameState::Cutscene, GameState::Dying, GameState::Restarting, GameState::Exiting, GameState::Inactive].iter() {
+        assert!(!state.load_waits());
+        assert!(!state.in_stage());
+    }
+}
+
+#[test]
+fn stopped_timer_is_a_cutscene() {
+    let mem = FakeMemory::new();
+    let addresses = Addresses::default();
+    let mut clock = StageClock::new();
+    mem.seed(TIME, &[0, 1, 0]);
+    let mut check = |frame: u32, state: GameState| {
+        mem.seed_u32(FRAME_COUNTER, frame);
+        clock.check(&mem, &addresses, state).unwrap()
+    };
+
+    assert_eq!(check(0, GameState::InLevel), GameState::InLevel);
+    assert_eq!(check(TIMER_STOPPED_FRAMES - 1, GameState::InLevel), GameState::InLevel);
+    assert_eq!(check(TIMER_STOPPED_FRAMES, GameState::InLevel), GameState::Cutscene);
+    // Other states pass through, and the timer's watched afresh after.
+    assert_eq!(check(TIMER_STOPPED_FRAMES + 1, GameState::Paused), GameState::Paused);
+    assert_eq!(check(TIMER_STOPPED_FRAMES * 3, GameState::InLevel), GameState::InLevel);
+}
+
+#[test]
+fn moving_timer_is_not_a_cutscene() {
+    let mem = FakeMemory::new();
+    let addresses = Addresses::default();
+    let mut clock = StageClock::new();
+    for frame in 0..TIMER_STOPPED_FRAMES * 2 {
+        mem.seed(TIME, &[0, (frame / 60) as u8, (frame % 60) as u8]);
+        mem.seed_u32(FRAME_COUNTER, frame);
+        assert_eq!(clock.check(&mem, &addresses, GameState::InLevel).unwrap(), GameState::InLevel);
+    }
+}
@@ -0,0 +1,183 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::LevelCollisionUnit;
+use onvars_tool::state_file::StateReader;
+use onvars_tool::SaveStateUnit;
+
+mod common;
+use common::{DYNCOL_COUNT, DYNCOL_LIST, DYNCOL_MAX};
+
+// (flags, model, task)
+type Entry = (u32, u32, u32);
+
+fn entry(object: u32, flags: u32) -> Entry {
+    (flags, 0x0400_0000 + object * 0x100, 0x0500_0000 + object * 0x100)
+}
+
+fn seed_list(mem: &FakeMemory, entries: &[Entry]) {
+    // Junk past the end of the list, like a real game would have.
+    mem.seed(DYNCOL_LIST, &vec![0xcc; DYNCOL_MAX * 0xc]);
+    for (idx, &(flags, model, task)) in entries.iter().enumerate() {
+        let address = DYNCOL_LIST + idx as u64 * 0xc;
+        mem.seed_u32(address, flags);
+        mem.seed_u32(address + 4, model);
+        mem.seed_u32(address + 8, task);
+    }
+    mem.seed(DYNCOL_COUNT, &(entries.len() as u16).to_le_bytes());
+}
+
+fn read_list(mem: &FakeMemory) -> Vec<Entry> {
+    let count = mem.bytes(DYNCOL_COUNT, 2);
+    let count = u16::from_le_bytes([count[0], count[1]]);
+    (0..count as u64)
+        .map(|idx| {
+            let word = |offset| {
+                let b = mem.bytes(DYNCOL_LIST + idx * 0xc + offset, 4);
+                u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+            };
+            (word(0), word(4), word(8))
+        })
+        .collect()
+}
+
+#[test]
+fn restores_flags_and_order() {
+    let mem = FakeMemory::new();
+    let saved = vec![entry(1, 0x1), entry(2, 0x2), entry(3, 0x3)];
+    seed_list(&mem, &saved);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_list(&mem, &[entry(3, 0x30), entry(1, 0x10), entry(2, 0x20)]);
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), saved);
+}
+
+#[test]
+fn destroyed_objects_are_not_written_back() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[entry(1, 0x1), entry(2, 0x2), entry(3, 0x3)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // Object 2 got destroyed after the save and its memory freed.
+    seed_list(&mem, &[entry(1, 0x1), entry(3, 0x3)]);
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1), entry(3, 0x3)]);
+}
+
+#[test]
+fn new_objects_are_kept() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[entry(1, 0x1)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_list(&mem, &[entry(4, 0x4), entry(1, 0x10)]);
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1), entry(4, 0x4)]);
+}
+
+#[test]
+fn load_only_writes_live_pointers() {
+    let mem = FakeMemory::new();
+    let saved: Vec<Entry> = (0..20).map(|object| entry(object, object)).collect();
+    seed_list(&mem, &saved);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // A different stage allocation: most of the old objects are gone and
+    // the list is shorter than it was.
+    let live: Vec<Entry> = [3, 7, 25, 26].iter().map(|&object| entry(object, 0)).collect();
+    seed_list(&mem, &live);
+    // Pretend the old list is still sitting past the end.
+    for (idx, &(flags, model, task)) in saved.iter().enumerate().skip(live.len()) {
+        let address = DYNCOL_LIST + idx as u64 * 0xc;
+        mem.seed_u32(address, flags);
+        mem.seed_u32(address + 4, model);
+        mem.seed_u32(address + 8, task);
+    }
+
+    let past_end = mem.bytes(DYNCOL_LIST + 4 * 0xc, 16 * 0xc);
+
+    unit.load(&mem).unwrap();
+    let after = read_list(&mem);
+    assert_eq!(after.len(), 4);
+    for &(_, model, task) in after.iter() {
+        assert!(live.iter().any(|&(_, m, t)| m == model && t == task));
+    }
+    // The stale entries past the end are left alone, not resurrected.
+    assert_eq!(mem.bytes(DYNCOL_LIST + 4 * 0xc, 16 * 0xc), past_end);
+}
+
+#[test]
+fn duplicate_entries_are_all_kept() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[entry(1, 0x1), entry(2, 0x2)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // Object 1 has two entries now. Only one of them has a saved partner,
+    // and the other one is left as it is.
+    seed_list(&mem, &[entry(1, 0x10), entry(2, 0x20), entry(1, 0x30)]);
+    let past_end = mem.bytes(DYNCOL_LIST + 3 * 0xc, 0xc);
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1), entry(2, 0x2), entry(1, 0x30)]);
+    assert_eq!(mem.bytes(DYNCOL_LIST + 3 * 0xc, 0xc), past_end);
+}
+
+#[test]
+fn saved_duplicates_need_a_live_partner_each() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[entry(1, 0x1), entry(1, 0x2)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_list(&mem, &[entry(1, 0x10)]);
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1)]);
+}
+
+#[test]
+fn null_tasks_are_not_restored() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[(0x1, 0, 0), entry(1, 0x1)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    unit.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1), (0x1, 0, 0)]);
+}
+
+#[test]
+fn state_round_trip() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, &[entry(1, 0x1), entry(2, 0x2)]);
+
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data.len(), 2 + 2 * 0xc);
+
+    let mut copy = LevelCollisionUnit::new(&Addresses::default());
+    copy.read_state(&mut StateReader::new(&data)).unwrap();
+    seed_list(&mem, &[entry(2, 0), entry(1, 0)]);
+    copy.load(&mem).unwrap();
+    assert_eq!(read_list(&mem), vec![entry(1, 0x1), entry(2, 0x2)]);
+}
+
+#[test]
+fn state_with_too_many_entries_is_rejected() {
+    let data = ((DYNCOL_MAX + 1) as u16).to_le_bytes();
+    let mut unit = LevelCollisionUnit::new(&Addresses::default());
+    let err = unit.read_state(&mut StateReader::new(&data)).unwrap_err();
+    assert_eq!(err, Error::InvalidSavestate { reason: "too many collision entries" });
+}
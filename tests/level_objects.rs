@@ -0,0 +1,271 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::{LevelObjectsUnit, OBJECT_LIST_COUNT};
+use onvars_tool::state_file::StateReader;
+use onvars_tool::SaveStateUnit;
+
+mod common;
+use common::{pattern, OBJECT_LISTS};
+
+const OBJECTS: u64 = 0x0400_0000;
+// SET data and entries are kept apart from the tasks, since they outlive
+// them.
+const SET_DATA: u64 = 0x0480_0000;
+const SET_ENTRIES: u64 = 0x0490_0000;
+
+// Where each part of synthetic object `n` lives.
+fn task(n: u32) -> u64 {
+    OBJECTS + n as u64 * 0x1000
+}
+fn set_data(n: u32) -> u64 {
+    SET_DATA + n as u64 * 0x10
+}
+fn set_entry(n: u32) -> u64 {
+    SET_ENTRIES + n as u64 * 0x20
+}
+fn data1(n: u32) -> u64 {
+    task(n) + 0x300
+}
+
+struct Object {
+    n: u32,
+    id: u16,
+    position: [f32; 3],
+    from_set: bool,
+}
+
+fn object(n: u32, id: u16, x: f32) -> Object {
+    Object { n, id, position: [x, 10.0, -5.0], from_set: true }
+}
+
+// Points object `obj`'s SET data at its SET entry and fills that in.
+fn seed_set_entry(mem: &FakeMemory, obj: &Object) {
+    mem.seed_u32(set_data(obj.n) + 0x8, set_entry(obj.n) as u32);
+    mem.seed(set_entry(obj.n), &obj.id.to_le_bytes());
+    for (axis, value) in obj.position.iter().enumerate() {
+        mem.seed(set_entry(obj.n) + 0x8 + 4 * axis as u64, &value.to_le_bytes());
+    }
+}
+
+// Links the objects into object list `list` (the other lists are empty)
+// and fills in their data with `seed`. Passing `circular` links the last
+// object back to the first.
+fn seed_list(mem: &FakeMemory, list: usize, objects: &[Object], seed: u32, circular: bool) {
+    mem.seed(OBJECT_LISTS, &[0; 4 * OBJECT_LIST_COUNT]);
+    let head = objects.first().map(|o| task(o.n) as u32).unwrap_or(0);
+    mem.seed_u32(OBJECT_LISTS + 4 * list as u64, head);
+
+    for (idx, obj) in objects.iter().enumerate() {
+        let next = match objects.get(idx + 1) {
+            Some(next) => task(next.n) as u32,
+            None if circular => head,
+            None => 0,
+        };
+        mem.seed(task(obj.n), &[0; 0x50]);
+        mem.seed_u32(task(obj.n) + 0x4, next);
+        mem.seed_u32(task(obj.n) + 0x30, if obj.from_set { set_data(obj.n) as u32 } else { 0 });
+        mem.seed_u32(task(obj.n) + 0x34, data1(obj.n) as u32);
+
+        seed_set_entry(mem, obj);
+        // Spawn state: load count and flags, then the pointer back to the
+        // task.
+        mem.seed(set_data(obj.n), &pattern(seed + obj.n + 0x100, 4));
+        mem.seed_u32(set_data(obj.n) + 0x4, task(obj.n) as u32);
+
+        // Data1, with its collision pointer at +0x2c.
+        mem.seed(data1(obj.n), &pattern(seed + obj.n, 0x2c));
+        mem.seed_u32(data1(obj.n) + 0x2c, 0x0500_0000 + obj.n);
+    }
+}
+
+#[test]
+fn objects_round_trip() {
+    let mem = FakeMemory::new();
+    let objects = [object(0, 3, 1.0), object(1, 4, 2.0), object(2, 3, 3.0)];
+    seed_list(&mem, 2, &objects, 1, false);
+    let before: Vec<Vec<u8>> = (0..3).map(|n| mem.bytes(data1(n), 0x30)).collect();
+    let spawn: Vec<Vec<u8>> = (0..3).map(|n| mem.bytes(set_data(n), 0xc)).collect();
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    seed_list(&mem, 2, &objects, 50, false);
+    unit.load(&mem).unwrap();
+
+    for n in 0..3 {
+        assert_eq!(mem.bytes(data1(n), 0x30), before[n as usize]);
+        assert_eq!(mem.bytes(set_data(n), 0xc), spawn[n as usize]);
+    }
+}
+
+#[test]
+fn objects_are_matched_by_type_and_position() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 0, &[object(0, 3, 1.0), object(1, 4, 2.0)], 1, false);
+    let first = mem.bytes(data1(0), 0x2c);
+    let second = mem.bytes(data1(1), 0x2c);
+    let first_spawn = mem.bytes(set_data(0), 4);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // Same objects, reallocated somewhere else and in a different order.
+    seed_list(&mem, 0, &[object(5, 4, 2.0), object(6, 3, 1.0), object(7, 3, 9.0)], 50, false);
+    let untouched = mem.bytes(data1(7), 0x2c);
+    unit.load(&mem).unwrap();
+
+    assert_eq!(mem.bytes(data1(6), 0x2c), first);
+    assert_eq!(mem.bytes(data1(5), 0x2c), second);
+    assert_eq!(mem.bytes(data1(7), 0x2c), untouched);
+    // The spawn state moves over, but each object keeps pointing at its
+    // own task.
+    assert_eq!(mem.bytes(set_data(6), 4), first_spawn);
+    for n in 5..8 {
+        assert_eq!(mem.bytes(set_data(n) + 0x4, 4), (task(n) as u32).to_le_bytes().to_vec());
+    }
+}
+
+#[test]
+fn load_never_writes_collision_pointers() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 0, &[object(0, 3, 1.0)], 1, false);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    seed_list(&mem, 0, &[object(4, 3, 1.0)], 2, false);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(data1(4) + 0x2c, 4), 0x0500_0004u32.to_le_bytes().to_vec());
+}
+
+#[test]
+fn destroyed_objects_respawn() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 0, &[object(0, 3, 1.0), object(1, 4, 2.0)], 1, false);
+    let spawn = mem.bytes(set_data(0), 4);
+    let second = mem.bytes(data1(1), 0x2c);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // Object 0 was destroyed: its task is freed and its SET data says so.
+    seed_list(&mem, 0, &[object(1, 4, 2.0)], 50, false);
+    mem.unmap(task(0), 0x1000);
+    mem.seed(set_data(0), &[0xff; 4]);
+    mem.seed_u32(set_data(0) + 0x4, 0);
+    unit.load(&mem).unwrap();
+
+    // Its spawn state is back, with the load count cleared so the game
+    // spawns it again. The freed task isn't.
+    let mut expected = spawn.clone();
+    expected[0] = 0;
+    assert_eq!(mem.bytes(set_data(0), 4), expected);
+    assert_eq!(mem.bytes(set_data(0) + 0x4, 4), vec![0; 4]);
+    assert_eq!(mem.bytes(data1(1), 0x2c), second);
+}
+
+#[test]
+fn reused_set_data_is_left_alone() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 0, &[object(0, 3, 1.0)], 1, false);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // The stage was reloaded and object 0's SET data now belongs to
+    // another entry.
+    seed_list(&mem, 0, &[], 50, false);
+    seed_set_entry(&mem, &object(0, 9, 7.0));
+    mem.seed(set_data(0), &[0xff; 8]);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(set_data(0), 8), vec![0xff; 8]);
+
+    // Or it isn't there at all.
+    mem.unmap(SET_DATA, 0x10);
+    unit.load(&mem).unwrap();
+}
+
+#[test]
+fn nan_positions_still_match() {
+    let mem = FakeMemory::new();
+    let mut odd = object(0, 3, 1.0);
+    odd.position[1] = f32::NAN;
+    seed_list(&mem, 0, &[odd], 1, false);
+    let before = mem.bytes(data1(0), 0x2c);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut odd = object(0, 3, 1.0);
+    odd.position[1] = f32::NAN;
+    seed_list(&mem, 0, &[odd], 50, false);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(data1(0), 0x2c), before);
+}
+
+#[test]
+fn objects_not_from_set_are_ignored() {
+    let mem = FakeMemory::new();
+    let mut effect = object(1, 0, 0.0);
+    effect.from_set = false;
+    seed_list(&mem, 0, &[object(0, 3, 1.0), effect], 1, false);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data.len(), 4 + 2 + 0xc + 4 + 4 + 0x2c);
+
+    seed_list(&mem, 0, &[object(0, 3, 1.0), object(1, 0, 0.0)], 50, false);
+    let effect_data = mem.bytes(data1(1), 0x2c);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(data1(1), 0x2c), effect_data);
+}
+
+#[test]
+fn circular_lists_stop_at_the_head() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 5, &[object(0, 3, 1.0), object(1, 4, 2.0)], 1, true);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data[..4], 2u32.to_le_bytes());
+}
+
+#[test]
+fn self_linked_task_does_not_hang() {
+    let mem = FakeMemory::new();
+    seed_list(&mem, 0, &[object(0, 3, 1.0), object(1, 4, 2.0)], 1, false);
+    mem.seed_u32(task(1) + 0x4, task(1) as u32);
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data[..4], 2u32.to_le_bytes());
+}
+
+#[test]
+fn state_round_trip() {
+    let mem = FakeMemory::new();
+    let objects = [object(0, 3, 1.0), object(1, 4, 2.0)];
+    seed_list(&mem, 1, &objects, 1, false);
+    let before: Vec<Vec<u8>> = (0..2).map(|n| mem.bytes(data1(n), 0x2c)).collect();
+
+    let mut unit = LevelObjectsUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+
+    let mut copy = LevelObjectsUnit::new(&Addresses::default());
+    copy.read_state(&mut StateReader::new(&data)).unwrap();
+    seed_list(&mem, 1, &objects, 50, false);
+    copy.load(&mem).unwrap();
+    for n in 0..2 {
+        assert_eq!(mem.bytes(data1(n), 0x2c), before[n as usize]);
+    }
+
+    let mut bad = LevelObjectsUnit::new(&Addresses::default());
+    let err = bad.read_state(&mut StateReader::new(&0x10000u32.to_le_bytes())).unwrap_err();
+    assert_eq!(err, Error::InvalidSavestate { reason: "too many objects" });
+}
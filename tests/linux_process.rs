@@ -0,0 +1,105 @@
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use onvars_tool::process_reader::{ProcessHandle, ProcessId};
+use onvars_tool::GameMemory;
+
+// Kills the child even if an assertion fails.
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Spawns a process that looks like Wine's view of the game: argv[0] is a
+// Windows path ending in the exe name.
+fn spawn_fake_game(exe_name: &str) -> ChildGuard {
+    let child = Command::new("sleep")
+        .arg0(format!("Z:\\games\\SA2\\{}", exe_name))
+        .arg("30")
+        .spawn()
+        .unwrap();
+    // Give exec a moment so /proc shows the new cmdline.
+    thread::sleep(Duration::from_millis(100));
+    ChildGuard(child)
+}
+
+// Finds a private writable mapping in the child to poke at.
+fn writable_region(pid: u32) -> (u64, u64) {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid)).unwrap();
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let range = fields.next()?;
+            let perms = fields.next()?;
+            if !perms.starts_with("rw") {
+                return None;
+            }
+            let mut bounds = range.split('-');
+            let start = u64::from_str_radix(bounds.next()?, 16).ok()?;
+            let end = u64::from_str_radix(bounds.next()?, 16).ok()?;
+            Some((start, end))
+        })
+        .next()
+        .unwrap()
+}
+
+#[test]
+fn finds_wine_process_by_exe_name() {
+    let name = format!("sonic2app-test-{}.exe", std::process::id());
+    let _child = spawn_fake_game(&name);
+
+    let handle = ProcessHandle::from_name_filter(|n| n.to_lowercase() == name).unwrap();
+    assert!(handle.is_some());
+    assert_eq!(handle.unwrap().get_name().unwrap(), name);
+}
+
+#[test]
+fn missing_process_is_none() {
+    let handle = ProcessHandle::from_name_filter(|n| n == "definitely-not-running.exe").unwrap();
+    assert!(handle.is_none());
+}
+
+#[test]
+fn reads_and_writes_child_memory() {
+    let child = spawn_fake_game("sonic2app.exe");
+    let pid = child.0.id();
+    let handle = ProcessHandle::open_process_read_info(ProcessId::from_raw(pid)).unwrap();
+
+    let (start, end) = writable_region(pid);
+    assert!(end - start >= 0x100);
+
+    let mut original = [0; 0x100];
+    assert_eq!(handle.read_data(start, &mut original).unwrap(), 0x100);
+
+    let pattern: Vec<u8> = (0..0x100).map(|i| i as u8 ^ 0x5a).collect();
+    assert_eq!(handle.write_data(start, &pattern).unwrap(), 0x100);
+
+    let mut readback = [0; 0x100];
+    handle.read_data(start, &mut readback).unwrap();
+    assert_eq!(&readback[..], &pattern[..]);
+
+    handle.write_u32(start, 0xdeadbeef).unwrap();
+    assert_eq!(handle.read_u32(start).unwrap(), 0xdeadbeef);
+    assert_eq!(handle.read_u8(start + 3).unwrap(), 0xde);
+
+    handle.write_data(start, &original).unwrap();
+}
+
+#[test]
+fn unmapped_address_is_an_error() {
+    let child = spawn_fake_game("sonic2app.exe");
+    let handle = ProcessHandle::open_process_read_info(ProcessId::from_raw(child.0.id())).unwrap();
+
+    let mut buf = [0; 4];
+    assert!(handle.read_data(0, &mut buf).is_err());
+    assert!(handle.write_data(0, &buf).is_err());
+}
@@ -0,0 +1,192 @@
+use std::cell::Cell;
+
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::load_scheduler::{LoadSchedule, LoadScheduler, MemoryCondition};
+use onvars_tool::sa2_units::{CharacterUnit, GravityUnit};
+use onvars_tool::state_file::StateReader;
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+const FLAG: u64 = 0x0300_0000;
+
+// Counts how many times it's been loaded.
+struct CountingUnit {
+    schedule: LoadSchedule,
+    loads: Cell<u32>,
+}
+
+impl CountingUnit {
+    fn new(schedule: LoadSchedule) -> CountingUnit {
+        CountingUnit {
+            schedule,
+            loads: Cell::new(0),
+        }
+    }
+}
+
+impl SaveStateUnit for CountingUnit {
+    fn name(&self) -> &'static str {
+        "CountingUnit"
+    }
+
+    fn save(&mut self, _handle: &dyn GameMemory) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn load(&self, _handle: &dyn GameMemory) -> Result<(), Error> {
+        self.loads.set(self.loads.get() + 1);
+        Ok(())
+    }
+
+    fn write_state(&self, _out: &mut Vec<u8>) {}
+
+    fn read_state(&mut self, _input: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn load_schedule(&self) -> LoadSchedule {
+        self.schedule
+    }
+}
+
+// Loads on frame 100, then polls twice a frame up to frame 100 + frames.
+fn run(unit: &CountingUnit, mem: &FakeMemory, frames: u32) -> u32 {
+    let units: [&dyn SaveStateUnit; 1] = [unit];
+    let mut scheduler = LoadScheduler::new();
+    assert!(scheduler.start(mem, &units, 100).is_empty());
+    for frame in 100..=100 + frames {
+        assert!(scheduler.update(mem, &units, frame).is_empty());
+        assert!(scheduler.update(mem, &units, frame).is_empty());
+    }
+    unit.loads.get()
+}
+
+#[test]
+fn immediate_loads_once() {
+    let mem = FakeMemory::new();
+    let unit = CountingUnit::new(LoadSchedule::Immediate);
+    assert_eq!(run(&unit, &mem, 10), 1);
+}
+
+#[test]
+fn after_frames_loads_twice() {
+    let mem = FakeMemory::new();
+    let unit = CountingUnit::new(LoadSchedule::AfterFrames(1));
+    assert_eq!(run(&unit, &mem, 10), 2);
+
+    let unit = CountingUnit::new(LoadSchedule::AfterFrames(3));
+    assert_eq!(run(&unit, &mem, 2), 1);
+    let unit = CountingUnit::new(LoadSchedule::AfterFrames(3));
+    assert_eq!(run(&unit, &mem, 3), 2);
+}
+
+#[test]
+fn every_frame_loads_on_each_frame() {
+    let mem = FakeMemory::new();
+    let unit = CountingUnit::new(LoadSchedule::EveryFrame(3));
+    assert_eq!(run(&unit, &mem, 10), 3);
+    let unit = CountingUnit::new(LoadSchedule::EveryFrame(1));
+    assert_eq!(run(&unit, &mem, 10), 1);
+}
+
+#[test]
+fn until_stops_once_condition_holds() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(FLAG, 0);
+    let unit = CountingUnit::new(LoadSchedule::Until {
+        condition: MemoryCondition::new(FLAG, 0x4, 0x4),
+        max_frames: 60,
+    });
+    let units: [&dyn SaveStateUnit; 1] = [&unit];
+    let mut scheduler = LoadScheduler::new();
+    scheduler.start(&mem, &units, 0);
+    scheduler.update(&mem, &units, 1);
+    scheduler.update(&mem, &units, 2);
+    assert_eq!(unit.loads.get(), 3);
+
+    mem.seed_u32(FLAG, 0x5);
+    scheduler.update(&mem, &units, 3);
+    assert!(scheduler.is_idle());
+    scheduler.update(&mem, &units, 4);
+    assert_eq!(unit.loads.get(), 3);
+}
+
+#[test]
+fn until_gives_up() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(FLAG, 0);
+    let unit = CountingUnit::new(LoadSchedule::Until {
+        condition: MemoryCondition::new(FLAG, 0xffff_ffff, 1),
+        max_frames: 5,
+    });
+    assert_eq!(run(&unit, &mem, 100), 6);
+}
+
+#[test]
+fn until_with_unreadable_condition_is_an_error() {
+    let mem = FakeMemory::new();
+    let unit = CountingUnit::new(LoadSchedule::Until {
+        condition: MemoryCondition::new(FLAG, 1, 1),
+        max_frames: 5,
+    });
+    let units: [&dyn SaveStateUnit; 1] = [&unit];
+    let mut scheduler = LoadScheduler::new();
+    scheduler.start(&mem, &units, 0);
+    assert_eq!(scheduler.update(&mem, &units, 1).len(), 1);
+    assert!(scheduler.is_idle());
+}
+
+#[test]
+fn units_only_reload_on_their_own_schedule() {
+    let mem = FakeMemory::new();
+    let once = CountingUnit::new(LoadSchedule::Immediate);
+    let twice = CountingUnit::new(LoadSchedule::AfterFrames(1));
+    let thrice = CountingUnit::new(LoadSchedule::EveryFrame(3));
+    let units: [&dyn SaveStateUnit; 3] = [&once, &twice, &thrice];
+
+    let mut scheduler = LoadScheduler::new();
+    scheduler.start(&mem, &units, 7);
+    for frame in 7..20 {
+        scheduler.update(&mem, &units, frame);
+    }
+    assert!(scheduler.is_idle());
+    assert_eq!((once.loads.get(), twice.loads.get(), thrice.loads.get()), (1, 2, 3));
+}
+
+#[test]
+fn starting_again_drops_pending_loads() {
+    let mem = FakeMemory::new();
+    let first = CountingUnit::new(LoadSchedule::AfterFrames(5));
+    let second = CountingUnit::new(LoadSchedule::Immediate);
+
+    let mut scheduler = LoadScheduler::new();
+    scheduler.start(&mem, &[&first], 0);
+    scheduler.start(&mem, &[&second], 1);
+    assert!(scheduler.is_idle());
+    scheduler.update(&mem, &[&first], 10);
+    assert_eq!(first.loads.get(), 1);
+
+    scheduler.start(&mem, &[&first], 20);
+    scheduler.cancel();
+    scheduler.update(&mem, &[&first], 30);
+    assert_eq!(first.loads.get(), 2);
+}
+
+#[test]
+fn frame_counter_wrapping_is_fine() {
+    let mem = FakeMemory::new();
+    let unit = CountingUnit::new(LoadSchedule::AfterFrames(2));
+    let units: [&dyn SaveStateUnit; 1] = [&unit];
+    let mut scheduler = LoadScheduler::new();
+    scheduler.start(&mem, &units, u32::MAX);
+    scheduler.update(&mem, &units, 0);
+    scheduler.update(&mem, &units, 1);
+    assert_eq!(unit.loads.get(), 2);
+}
+
+#[test]
+fn built_in_unit_schedules() {
+    assert_eq!(CharacterUnit::new(&Addresses::default()).load_schedule(), LoadSchedule::AfterFrames(1));
+    assert_eq!(GravityUnit::new(&Addresses::default()).load_schedule(), LoadSchedule::Immediate);
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use onvars_tool::addresses::Addresses;
+use onvars_tool::build::BuildInfo;
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::{FakeMemory, PAGE_SIZE};
+use onvars_tool::memory_image::MemoryImage;
+use onvars_tool::sa2_units::{CharacterUnit, GravityUnit};
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+// A minidump with a MemoryListStream (`memory64` false) or a
+// Memory64ListStream holding `ranges`, behind an unrelated stream.
+fn minidump(ranges: &[(u64, Vec<u8>)], memory64: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"MDMP");
+    data.extend_from_slice(&0xa793u32.to_le_bytes());
+    data.extend_from_slice(&2u32.to_le_bytes());
+    data.extend_from_slice(&32u32.to_le_bytes());
+    data.resize(32, 0);
+
+    // The directory, then the memory list right after it.
+    let list = 32 + 2 * 12;
+    let descriptors = if memory64 { list + 16 } else { list + 4 };
+    let contents = descriptors + ranges.len() * 16;
+    // ThreadListStream, which should be skipped.
+    data.extend_from_slice(&3u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&(if memory64 { 9u32 } else { 5u32 }).to_le_bytes());
+    data.extend_from_slice(&((contents - list) as u32).to_le_bytes());
+    data.extend_from_slice(&(list as u32).to_le_bytes());
+
+    if memory64 {
+        data.extend_from_slice(&(ranges.len() as u64).to_le_bytes());
+        data.extend_from_slice(&(contents as u64).to_le_bytes());
+        for &(address, ref bytes) in ranges.iter() {
+            data.extend_from_slice(&address.to_le_bytes());
+            data.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        }
+    } else {
+        data.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+        let mut rva = contents;
+        for &(address, ref bytes) in ranges.iter() {
+            data.extend_from_slice(&address.to_le_bytes());
+            data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(rva as u32).to_le_bytes());
+            rva += bytes.len();
+        }
+    }
+    for (_, bytes) in ranges.iter() {
+        data.extend_from_slice(bytes);
+    }
+    data
+}
+
+// Every page of `mem` touched by `regions`, like a dump would have them.
+fn dump_pages(mem: &FakeMemory, regions: &[Region]) -> Vec<(u64, Vec<u8>)> {
+    let pages: BTreeSet<u64> = regions.iter()
+        .flat_map(|r| r.address / PAGE_SIZE..=(r.address + r.len as u64 - 1) / PAGE_SIZE)
+        .collect();
+    pages.iter()
+        .map(|&page| (page * PAGE_SIZE, mem.bytes(page * PAGE_SIZE, PAGE_SIZE as usize)))
+        .collect()
+}
+
+fn game_regions() -> Vec<Region> {
+    let mut regions = character_regions(4);
+    regions.push(Region { address: CHARACTER_PTR, len: 4 });
+    regions.push(Region { address: TASK, len: 0x50 });
+    regions.push(Region { address: 0x400000, len: 0x400 });
+    regions
+}
+
+fn saved_state(mem: &dyn GameMemory) -> Vec<u8> {
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    data
+}
+
+#[test]
+fn units_save_from_a_minidump() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 4, 1);
+    seed_pe_header(&mem, 0x1234_5678);
+    let expected = saved_state(&mem);
+
+    for &memory64 in [false, true].iter() {
+        let image = MemoryImage::parse_minidump(&minidump(&dump_pages(&mem, &game_regions()), memory64)).unwrap();
+        assert_eq!(saved_state(&image), expected);
+        assert_eq!(BuildInfo::detect(&image).unwrap(), BuildInfo::detect(&mem).unwrap());
+    }
+}
+
+#[test]
+fn images_are_read_only() {
+    let mut image = MemoryImage::new();
+    image.add_region(GRAVITY, pattern(1, 0xc)).unwrap();
+
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&image).unwrap();
+    let err = unit.load(&image).unwrap_err();
+    assert_eq!(err.root(), &Error::ReadOnly { address: GRAVITY, requested: 0xc });
+    assert_eq!(image.read_u8(GRAVITY).unwrap(), pattern(1, 1)[0]);
+}
+
+#[test]
+fn reads_cross_adjacent_regions_but_not_gaps() {
+    let mut image = MemoryImage::new();
+    image.add_region(0x1000, vec![1, 2]).unwrap();
+    image.add_region(0x1002, vec![3, 4]).unwrap();
+    image.add_region(0x1008, vec![5]).unwrap();
+    assert_eq!(image.region_count(), 3);
+
+    let mut buf = [0; 4];
+    image.read_exact(0x1000, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4]);
+
+    assert_eq!(image.read_data(0x1003, &mut buf).unwrap(), 1);
+    assert_eq!(image.read_exact(0x1003, &mut buf).unwrap_err(), Error::PartialAccess {
+        kind: AccessKind::Read,
+        address: 0x1003,
+        requested: 4,
+        transferred: 1,
+    });
+    assert_eq!(image.read_data(0x1004, &mut buf).unwrap_err(), Error::Access {
+        kind: AccessKind::Read,
+        address: 0x1004,
+        requested: 4,
+        code: None,
+    });
+}
+
+#[test]
+fn overlapping_regions_are_rejected() {
+    let overlapping = Err(Error::InvalidImage { reason: "overlapping regions" });
+    let mut image = MemoryImage::new();
+    image.add_region(0x1000, vec![0; 0x10]).unwrap();
+    assert_eq!(image.add_region(0x100f, vec![0; 2]), overlapping);
+    assert_eq!(image.add_region(0xff0, vec![0; 0x11]), overlapping);
+    assert_eq!(image.add_region(0x1004, vec![0; 2]), overlapping);
+    image.add_region(0xff0, vec![0; 0x10]).unwrap();
+
+    let dump = minidump(&[(0x1000, vec![0; 0x10]), (0x1008, vec![0; 0x10])], true);
+    assert_eq!(MemoryImage::parse_minidump(&dump).unwrap_err(), Error::InvalidImage { reason: "overlapping regions" });
+}
+
+#[test]
+fn bad_minidumps_are_rejected() {
+    let invalid = |reason| Error::InvalidImage { reason };
+    let dump = minidump(&[(0x1000, vec![1; 0x10])], false);
+    assert_eq!(MemoryImage::parse_minidump(b"MDMX").unwrap_err(), invalid("not a minidump"));
+    assert_eq!(MemoryImage::parse_minidump(&dump[..dump.len() - 1]).unwrap_err(), invalid("minidump is truncated"));
+    assert_eq!(MemoryImage::parse_minidump(&dump[..40]).unwrap_err(), invalid("minidump is truncated"));
+
+    let mut no_memory = dump.clone();
+    no_memory[8] = 1;
+    assert_eq!(MemoryImage::parse_minidump(&no_memory).unwrap_err(), invalid("minidump has no memory in it"));
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("onvars_tool_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn manifests_list_region_files() {
+    let dir = TempDir::new("manifest");
+    fs::write(dir.0.join("gravity.bin"), pattern(1, 0xc)).unwrap();
+    fs::write(dir.0.join("time and more.bin"), pattern(2, 3)).unwrap();
+    fs::write(dir.0.join("regions.txt"), format!("\
+        # address  file\n\
+        {:#x}  gravity.bin\n\
+        \n\
+        {}  time and more.bin\n\
+    ", GRAVITY, TIME)).unwrap();
+
+    let image = MemoryImage::open(&dir.0.join("regions.txt")).unwrap();
+    assert_eq!(image.region_count(), 2);
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&image).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data, pattern(1, 0xc));
+    let mut time = [0; 3];
+    image.read_exact(TIME, &mut time).unwrap();
+    assert_eq!(time.to_vec(), pattern(2, 3));
+
+    // Minidumps are recognized by their signature, whatever they're called.
+    fs::write(dir.0.join("crash.txt"), minidump(&[(GRAVITY, pattern(3, 0xc))], false)).unwrap();
+    let image = MemoryImage::open(&dir.0.join("crash.txt")).unwrap();
+    assert_eq!(image.read_u8(GRAVITY).unwrap(), pattern(3, 1)[0]);
+}
+
+#[test]
+fn bad_manifest_lines_are_rejected() {
+    let dir = TempDir::new("bad_manifest");
+    fs::write(dir.0.join("a.bin"), [0; 4]).unwrap();
+    let parse = |text| MemoryImage::parse_manifest(text, &dir.0).map(|_| ());
+    let invalid = |line, reason| Err(Error::InvalidConfig { line, reason });
+    assert_eq!(parse("0x1000"), invalid(1, "expected \"address file\""));
+    assert_eq!(parse("\nsomewhere a.bin"), invalid(2, "address is not a number"));
+    assert_eq!(parse("0x1000 a.bin\n0x1002 a.bin"), invalid(2, "overlapping regions"));
+    assert!(matches!(parse("0x1000 missing.bin"), Err(Error::Io { .. })));
+}
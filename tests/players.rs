@@ -0,0 +1,93 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::AllPlayersUnit;
+use onvars_tool::SaveStateUnit;
+
+mod common;
+use common::*;
+
+const CAMERA_ELEMENTS: u64 = 0x01dcff40;
+const CAMERA_ELEMENT_SIZE: u64 = 0x24d8;
+
+fn two_player_regions(p1: u8, p2: u8) -> Vec<Region> {
+    let mut regions = player_regions(0, p1);
+    regions.extend(player_regions(1, p2));
+    for player in 0..2 {
+        regions.push(Region {
+            address: CAMERA_ELEMENTS + CAMERA_ELEMENT_SIZE * player,
+            len: CAMERA_ELEMENT_SIZE as usize,
+        });
+    }
+    regions
+}
+
+fn seed_two_players(mem: &FakeMemory, p1: u8, p2: u8, seed: u32) {
+    seed_player(mem, 0, p1, seed);
+    seed_player(mem, 1, p2, seed + 20);
+    clear_player(mem, 2);
+    clear_player(mem, 3);
+    mem.seed(CAMERA_ELEMENTS, &pattern(seed + 40, 4 * CAMERA_ELEMENT_SIZE as usize));
+}
+
+#[test]
+fn two_player_round_trip() {
+    let mem = FakeMemory::new();
+    seed_two_players(&mem, 0, 1, 1);
+    let regions = two_player_regions(0, 1);
+    let before = snapshot(&mem, &regions);
+
+    let mut unit = AllPlayersUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_two_players(&mem, 0, 1, 50);
+    assert_ne!(snapshot(&mem, &regions), before);
+
+    unit.load(&mem).unwrap();
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn empty_players_are_left_alone() {
+    let mem = FakeMemory::new();
+    seed_two_players(&mem, 4, 5, 1);
+    let p3_camera = CAMERA_ELEMENTS + 2 * CAMERA_ELEMENT_SIZE;
+
+    let mut unit = AllPlayersUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    mem.seed(p3_camera, &[0xcc; 0x10]);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(p3_camera, 0x10), vec![0xcc; 0x10]);
+}
+
+#[test]
+fn swapped_characters_are_rejected_before_writing() {
+    let mem = FakeMemory::new();
+    seed_two_players(&mem, 0, 4, 1);
+
+    let mut unit = AllPlayersUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_two_players(&mem, 0, 5, 50);
+    let regions = two_player_regions(0, 5);
+    let before = snapshot(&mem, &regions);
+    // Knuckles and Rouge share a physics layout, but they're not the same
+    // character, so this still counts as a different player.
+    let err = unit.load(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::PlayerMismatch { player: 2, saved: Some(4), current: Some(5) });
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn player_leaving_is_rejected() {
+    let mem = FakeMemory::new();
+    seed_two_players(&mem, 0, 1, 1);
+
+    let mut unit = AllPlayersUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    clear_player(&mem, 1);
+    let err = unit.load(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::PlayerMismatch { player: 2, saved: Some(1), current: None });
+    assert_eq!(err.to_string(), "AllPlayersUnit: player 2 is nobody but savestate has character 1");
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use onvars_tool::addresses::Addresses;
+use onvars_tool::bindings::Bindings;
+use onvars_tool::dry_run::DryRun;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::session::{Session, UnitOptions};
+use onvars_tool::trace::{Recorder, Replay, Trace};
+use onvars_tool::GameMemory;
+
+mod common;
+use common::*;
+
+const BUTTONS: u64 = 0x01A52C4C;
+const FRAME_COUNTER: u64 = 0x0174b03c;
+const LEFT: u32 = 0x1;
+const RIGHT: u32 = 0x2;
+
+// The default addresses, but with smaller camera structs to keep the
+// trace short.
+fn addresses() -> Addresses {
+    Addresses {
+        camera_size: 0x100,
+        past_position_table_size: 0x30,
+        ..Addresses::default()
+    }
+}
+
+fn trace_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/traces/save_and_load.trace")
+}
+
+fn seed_game(mem: &FakeMemory, seed: u32) {
+    seed_character(mem, 0, seed);
+    seed_camera(mem, seed);
+    mem.seed(TIME, &pattern(seed + 20, 3));
+    mem.seed(GRAVITY, &pattern(seed + 21, 0xc));
+    mem.seed(LIVES, &pattern(seed + 22, 4));
+    mem.seed(RINGS, &pattern(seed + 23, 8));
+    mem.seed(SCORE, &pattern(seed + 24, 4));
+}
+
+// A fresh stage: Sonic in level 13, with no objects.
+fn seed_stage(mem: &FakeMemory) {
+    seed_game(mem, 1);
+    mem.seed(OBJECT_LISTS, &[0; 0x20]);
+    mem.seed_u32(LEVEL, 13);
+    mem.seed_u32(GAME_STATE, 0x10);
+    mem.seed_u32(BUTTONS, 0);
+    mem.seed_u32(FRAME_COUNTER, 0);
+}
+
+// Saves on frame 2, lets the game carry on, then loads on frame 4 and
+// polls once more for the second pass. Loads write through `load_handle`.
+// `mem` is the game behind `handle`, if there is one; a replay doesn't
+// need it.
+fn play(handle: &dyn GameMemory, load_handle: &dyn GameMemory, mem: Option<&FakeMemory>) -> (Session, Vec<String>) {
+    let mut session = Session::new(&UnitOptions::new(), &addresses(), Bindings::default(), 0);
+    let mut messages = Vec::new();
+    for (frame, buttons) in [0, LEFT, 0, RIGHT, 0].iter().enumerate() {
+        if let Some(mem) = mem {
+            mem.seed_u32(BUTTONS, *buttons);
+            mem.seed_u32(FRAME_COUNTER, frame as u32 + 1);
+            if frame == 2 {
+                seed_game(mem, 50);
+            }
+        }
+        session.poll(handle, load_handle).unwrap();
+        messages.extend(session.take_messages());
+    }
+    (session, messages)
+}
+
+#[test]
+fn save_then_load_restores_the_game() {
+    let mem = FakeMemory::new();
+    seed_stage(&mem);
+    let mut regions = character_regions(0);
+    regions.push(Region { address: CAMERA, len: 0x100 });
+    regions.push(Region { address: PAST_POSITIONS, len: 0x30 });
+    let before = snapshot(&mem, &regions);
+
+    let (session, messages) = play(&mem, &mem, Some(&mem));
+    assert_eq!(messages, vec!["Saving state to slot 1", "Loading state from slot 1"]);
+    assert!(session.slot(0).valid);
+    assert!(!session.is_loading());
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn dry_run_loads_leave_the_game_alone() {
+    let mem = FakeMemory::new();
+    seed_stage(&mem);
+    let dry_run = DryRun::new(&mem);
+    let (session, messages) = play(&mem, &dry_run, Some(&mem));
+    assert_eq!(messages, vec!["Saving state to slot 1", "Loading state from slot 1"]);
+    assert!(!session.is_loading());
+    assert!(!dry_run.new_writes().is_empty());
+    assert_eq!(mem.bytes(GRAVITY, 0xc), pattern(50 + 21, 0xc));
+}
+
+#[test]
+fn cutscenes_block_loading() {
+    let mem = FakeMemory::new();
+    seed_stage(&mem);
+    let mut session = Session::new(&UnitOptions::new(), &Addresses::default(), Bindings::default(), 0);
+    let mut poll = |frame: u32, buttons: u32| {
+        mem.seed_u32(BUTTONS, buttons);
+        mem.seed_u32(FRAME_COUNTER, frame);
+        session.poll(&mem, &mem).unwrap();
+        session.take_messages()
+    };
+    assert!(poll(1, 0).is_empty());
+    assert_eq!(poll(2, LEFT), vec!["Saving state to slot 1"]);
+
+    // The stage timer hasn't moved for half a second.
+    for frame in 3..40 {
+        poll(frame, 0);
+    }
+    assert_eq!(poll(40, RIGHT), vec!["Cannot load state: a cutscene is playing."]);
+    poll(41, 0);
+
+    // Once it's going again, so is loading.
+    mem.seed(TIME, &[1, 2, 3]);
+    poll(42, 0);
+    assert_eq!(poll(43, RIGHT), vec!["Loading state from slot 1"]);
+}
+
+#[test]
+fn sessions_without_a_slot_dir_write_no_files() {
+    let session = Session::new(&UnitOptions::new(), &Addresses::default(), Bindings::default(), 0);
+    assert_eq!(session.slot_path(0), None);
+}
+
+#[test]
+fn recorded_session_replays() {
+    let trace = Trace::read_file(&trace_path()).unwrap();
+    let replay = Replay::new(trace);
+    let (session, messages) = play(&replay, &replay, None);
+    replay.finish().unwrap();
+    assert_eq!(messages, vec!["Saving state to slot 1", "Loading state from slot 1"]);
+    assert!(session.slot(0).valid);
+}
+
+// Run with --ignored to record tests/traces/save_and_load.trace again,
+// after changing what a session reads or writes.
+#[test]
+#[ignore]
+fn record_save_and_load_trace() {
+    let mem = FakeMemory::new();
+    seed_stage(&mem);
+    let recorder = Recorder::create(&mem, &trace_path()).unwrap();
+    play(&recorder, &recorder, Some(&mem));
+}
@@ -0,0 +1,228 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::build::BuildInfo;
+use onvars_tool::error::Error;
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::{CameraUnit, CharacterUnit, GravityUnit, LevelCollisionUnit, TimeUnit};
+use onvars_tool::state_file::{self, StateHeader, StateReader};
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+fn seed_game(mem: &FakeMemory, seed: u32) {
+    seed_character(mem, 4, seed);
+    seed_camera(mem, seed);
+    mem.seed(0x0174AFDB, &pattern(seed, 3));
+    mem.seed(0x01DE94A0, &pattern(seed, 0xc));
+    mem.seed_u32(LEVEL, 13);
+    seed_pe_header(mem, 0x3d0e_5a2b);
+}
+
+fn build_id(mem: &FakeMemory) -> u32 {
+    BuildInfo::detect(mem).unwrap().state_id()
+}
+
+fn all_units() -> Vec<Box<dyn SaveStateUnit>> {
+    vec![
+        Box::new(CharacterUnit::new(&Addresses::default())),
+        Box::new(CameraUnit::new(&Addresses::default())),
+        Box::new(TimeUnit::new(&Addresses::default())),
+        Box::new(GravityUnit::new(&Addresses::default())),
+    ]
+}
+
+fn encode_units(mem: &FakeMemory, units: &mut [Box<dyn SaveStateUnit>]) -> Vec<u8> {
+    for unit in units.iter_mut() {
+        unit.save(mem).unwrap();
+    }
+    let header = StateHeader::read(mem, &Addresses::default(), build_id(mem)).unwrap();
+    let refs: Vec<&dyn SaveStateUnit> = units.iter().map(|unit| &**unit).collect();
+    state_file::encode(&header, &refs)
+}
+
+fn decode_units(data: &[u8], units: &mut [Box<dyn SaveStateUnit>]) -> Result<StateHeader, Error> {
+    let mut refs: Vec<&mut dyn SaveStateUnit> = Vec::new();
+    for unit in units.iter_mut() {
+        refs.push(&mut **unit);
+    }
+    state_file::decode(data, &mut refs).map(|decoded| decoded.header)
+}
+
+fn state(unit: &dyn SaveStateUnit) -> Vec<u8> {
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    data
+}
+
+#[test]
+fn header_reads_current_game() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+
+    assert_eq!(StateHeader::read(&mem, &Addresses::default(), build_id(&mem)).unwrap(), StateHeader {
+        build_id: 0x3d0e_5a2b,
+        level: 13,
+        character: 4,
+    });
+}
+
+#[test]
+fn file_round_trip_restores_memory() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let mut regions = character_regions(4);
+    regions.extend(camera_regions());
+    regions.push(Region { address: 0x0174AFDB, len: 3 });
+    regions.push(Region { address: 0x01DE94A0, len: 0xc });
+    let before = snapshot(&mem, &regions);
+
+    let data = encode_units(&mem, &mut all_units());
+    assert_eq!(&data[..4], b"OVST");
+
+    // A fresh session: new units, different game state.
+    seed_game(&mem, 50);
+    let mut units = all_units();
+    let header = decode_units(&data, &mut units).unwrap();
+    assert_eq!(header.level, 13);
+    assert_eq!(header.character, 4);
+
+    for unit in units.iter() {
+        unit.load(&mem).unwrap();
+    }
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn encoding_is_stable() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let data = encode_units(&mem, &mut all_units());
+
+    let mut units = all_units();
+    decode_units(&data, &mut units).unwrap();
+    let header = StateHeader::read(&mem, &Addresses::default(), build_id(&mem)).unwrap();
+    let refs: Vec<&dyn SaveStateUnit> = units.iter().map(|unit| &**unit).collect();
+    assert_eq!(state_file::encode(&header, &refs), data);
+}
+
+#[test]
+fn corrupt_chunk_is_rejected() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let mut data = encode_units(&mem, &mut all_units());
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+
+    let err = decode_units(&data, &mut all_units()).unwrap_err();
+    assert_eq!(err, Error::InvalidSavestate { reason: "chunk checksum mismatch" });
+}
+
+#[test]
+fn bad_magic_and_version_are_rejected() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let data = encode_units(&mem, &mut all_units());
+
+    let mut bad_magic = data.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(decode_units(&bad_magic, &mut all_units()).unwrap_err(),
+        Error::InvalidSavestate { reason: "not a savestate file" });
+
+    let mut bad_version = data.clone();
+    bad_version[4] = 0xff;
+    assert_eq!(decode_units(&bad_version, &mut all_units()).unwrap_err(),
+        Error::UnsupportedVersion { version: 0xff });
+
+    assert!(decode_units(&data[..data.len() - 10], &mut all_units()).is_err());
+}
+
+#[test]
+fn missing_unit_is_rejected() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let mut units: Vec<Box<dyn SaveStateUnit>> = vec![Box::new(GravityUnit::new(&Addresses::default()))];
+    let data = encode_units(&mem, &mut units);
+
+    let err = decode_units(&data, &mut all_units()).unwrap_err();
+    assert_eq!(err.path(), &["CharacterUnit"]);
+    assert_eq!(err.root(), &Error::InvalidSavestate { reason: "missing unit chunk" });
+}
+
+#[test]
+fn missing_optional_unit_is_skipped() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let data = encode_units(&mem, &mut all_units());
+
+    // A file from before LevelCollisionUnit existed.
+    let mut units = all_units();
+    units.push(Box::new(LevelCollisionUnit::new(&Addresses::default())));
+    let before = state(&*units[4]);
+    let mut refs: Vec<&mut dyn SaveStateUnit> = Vec::new();
+    for unit in units.iter_mut() {
+        refs.push(&mut **unit);
+    }
+    let decoded = state_file::decode(&data, &mut refs).unwrap();
+    assert_eq!(decoded.header.level, 13);
+    assert_eq!(decoded.missing, vec!["LevelCollisionUnit"]);
+    assert_eq!(state(&*units[4]), before);
+}
+
+// Writes a GravityUnit chunk that's a byte too long.
+struct LongGravity;
+
+impl SaveStateUnit for LongGravity {
+    fn name(&self) -> &'static str {
+        "GravityUnit"
+    }
+
+    fn save(&mut self, _handle: &dyn GameMemory) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn load(&self, _handle: &dyn GameMemory) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[0; 0xd]);
+    }
+
+    fn read_state(&mut self, _input: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn bad_chunk_leaves_every_unit_alone() {
+    let mem = FakeMemory::new();
+    seed_game(&mem, 1);
+    let mut units = all_units();
+    for unit in units.iter_mut() {
+        unit.save(&mem).unwrap();
+    }
+    let header = StateHeader::read(&mem, &Addresses::default(), build_id(&mem)).unwrap();
+    let mut refs: Vec<&dyn SaveStateUnit> = units.iter().map(|unit| &**unit).collect();
+    refs[3] = &LongGravity;
+    let data = state_file::encode(&header, &refs);
+
+    // The checksums are fine, so CharacterUnit, CameraUnit and TimeUnit
+    // get read before GravityUnit's chunk turns out to be too long.
+    seed_game(&mem, 50);
+    let mut units = all_units();
+    for unit in units.iter_mut() {
+        unit.save(&mem).unwrap();
+    }
+    let before: Vec<Vec<u8>> = units.iter().map(|unit| state(&**unit)).collect();
+    let err = decode_units(&data, &mut units).unwrap_err();
+    assert_eq!(err.path(), &["GravityUnit"]);
+    assert_eq!(err.root(), &Error::InvalidSavestate { reason: "chunk longer than unit data" });
+    let after: Vec<Vec<u8>> = units.iter().map(|unit| state(&**unit)).collect();
+    assert_eq!(after, before);
+}
+
+#[test]
+fn crc32_matches_reference() {
+    assert_eq!(state_file::crc32(b""), 0);
+    assert_eq!(state_file::crc32(b"123456789"), 0xcbf4_3926);
+}
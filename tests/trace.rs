@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::{CharacterUnit, GravityUnit};
+use onvars_tool::state_file::StateReader;
+use onvars_tool::trace::{Recorder, Replay, Trace, TraceEntry};
+use onvars_tool::{ByteOrder, GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+// A trace file we can look at while it's still being written.
+#[derive(Clone,Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuf {
+    fn trace(&self) -> Trace {
+        Trace::parse(&String::from_utf8(self.0.borrow().clone()).unwrap()).unwrap()
+    }
+}
+
+fn record(mem: &FakeMemory) -> (Recorder<&FakeMemory>, SharedBuf) {
+    let out = SharedBuf::default();
+    (Recorder::new(mem, Box::new(out.clone())).unwrap(), out)
+}
+
+// Saves player 1's character, then loads it back.
+fn save_and_load(mem: &dyn GameMemory) -> Vec<u8> {
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(mem).unwrap();
+    unit.load(mem).unwrap();
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    data
+}
+
+#[test]
+fn entries_round_trip_through_lines() {
+    let entries = [
+        TraceEntry::Read { address: 0x01dea6e0, requested: 4, result: Ok(vec![0xe0, 0xa6, 0xde, 0x01]) },
+        TraceEntry::Read { address: 0x1000, requested: 4, result: Ok(vec![1]) },
+        TraceEntry::Read { address: 0x1000, requested: 4, result: Ok(vec![]) },
+        TraceEntry::Read { address: 0, requested: 8, result: Err(Some(299)) },
+        TraceEntry::Write { address: 0x0174b050, data: vec![0x2a, 0, 0, 0], result: Ok(4) },
+        TraceEntry::Write { address: 0x0174b050, data: vec![0x2a], result: Err(None) },
+    ];
+    for entry in entries.iter() {
+        assert_eq!(TraceEntry::parse(&entry.to_line()), Ok(entry.clone()));
+    }
+    assert_eq!(entries[0].to_line(), "r 0x01dea6e0 4 e0a6de01");
+    assert_eq!(entries[2].to_line(), "r 0x00001000 4 -");
+    assert_eq!(entries[3].to_line(), "r 0x00000000 8 error 299");
+    assert_eq!(entries[5].to_line(), "w 0x0174b050 2a error");
+}
+
+#[test]
+fn recorded_session_replays() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 4, 1);
+    let (recorder, out) = record(&mem);
+    let recorded = save_and_load(&recorder);
+
+    let trace = out.trace();
+    assert_eq!(trace.byte_order, ByteOrder::Little);
+    assert_eq!(trace.pointer_size, 4);
+    assert!(trace.entries.iter().any(|entry| matches!(*entry, TraceEntry::Write { .. })));
+
+    // No game memory needed.
+    let replay = Replay::new(trace);
+    assert_eq!(save_and_load(&replay), recorded);
+    assert!(replay.is_over());
+    replay.finish().unwrap();
+}
+
+#[test]
+fn different_writes_are_mismatches() {
+    let mem = FakeMemory::new();
+    mem.seed(GRAVITY, &pattern(1, 0xc));
+    let (recorder, out) = record(&mem);
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&recorder).unwrap();
+    unit.load(&recorder).unwrap();
+
+    // Replaying a different savestate writes different bytes.
+    let replay = Replay::new(out.trace());
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&replay).unwrap();
+    unit.read_state(&mut StateReader::new(&pattern(2, 0xc))).unwrap();
+    let mismatch = Error::TraceMismatch { entry: 2, reason: "wrote different bytes" };
+    assert_eq!(unit.load(&replay).unwrap_err().root(), &mismatch);
+    assert!(replay.is_over());
+    assert_eq!(replay.finish(), Err(mismatch.clone()));
+    // And it stays failed.
+    assert_eq!(replay.read_u8(GRAVITY).unwrap_err(), mismatch);
+}
+
+#[test]
+fn calls_have_to_come_in_order() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1000, &[1, 2, 3, 4]);
+    let (recorder, out) = record(&mem);
+    recorder.read_u32(0x1000).unwrap();
+    recorder.write_u32(0x1000, 5).unwrap();
+
+    let replay = Replay::new(out.trace());
+    assert_eq!(replay.read_u8(0x1000).unwrap_err(), Error::TraceMismatch { entry: 1, reason: "read a different length" });
+    let replay = Replay::new(out.trace());
+    assert_eq!(replay.read_u32(0x1004).unwrap_err(), Error::TraceMismatch { entry: 1, reason: "read from a different address" });
+    let replay = Replay::new(out.trace());
+    assert_eq!(replay.write_u32(0x1000, 5).unwrap_err(), Error::TraceMismatch { entry: 1, reason: "expected a read" });
+
+    let replay = Replay::new(out.trace());
+    assert_eq!(replay.read_u32(0x1000).unwrap(), 0x0403_0201);
+    assert!(!replay.is_over());
+    assert_eq!(replay.finish(), Err(Error::TraceMismatch { entry: 2, reason: "calls were left over" }));
+    assert_eq!(replay.read_u32(0x1000).unwrap_err(), Error::TraceMismatch { entry: 2, reason: "expected a write" });
+}
+
+#[test]
+fn running_past_the_end_is_not_a_mismatch() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1000, &[1, 2, 3, 4]);
+    let (recorder, out) = record(&mem);
+    recorder.read_u32(0x1000).unwrap();
+
+    let replay = Replay::new(out.trace());
+    replay.read_u32(0x1000).unwrap();
+    assert_eq!(replay.read_u32(0x1000).unwrap_err(), Error::TraceMismatch { entry: 2, reason: "the trace has ended" });
+    replay.finish().unwrap();
+}
+
+#[test]
+fn failures_are_replayed() {
+    let mem = FakeMemory::new();
+    mem.seed(0x1ffe, &[7, 8]);
+    let (recorder, out) = record(&mem);
+    let mut buf = [0; 4];
+    assert!(recorder.read_data(0x3000, &mut buf).is_err());
+    assert!(recorder.write_data(0x3000, &buf).is_err());
+
+    let replay = Replay::new(out.trace());
+    assert_eq!(replay.read_data(0x3000, &mut buf).unwrap_err(), Error::Access {
+        kind: AccessKind::Read,
+        address: 0x3000,
+        requested: 4,
+        code: None,
+    });
+    assert_eq!(replay.write_data(0x3000, &buf).unwrap_err(), Error::Access {
+        kind: AccessKind::Write,
+        address: 0x3000,
+        requested: 4,
+        code: None,
+    });
+
+    // Short reads come back short.
+    let trace = Trace::parse("order big\npointer 8\nr 0x1ffe 4 0708\n").unwrap();
+    let replay = Replay::new(trace);
+    assert_eq!(replay.byte_order(), ByteOrder::Big);
+    assert_eq!(replay.pointer_size(), 8);
+    assert_eq!(replay.read_data(0x1ffe, &mut buf).unwrap(), 2);
+    assert_eq!(buf[..2], [7, 8]);
+}
+
+#[test]
+fn bad_trace_lines_are_rejected() {
+    let invalid = |line, reason| Err(Error::InvalidConfig { line, reason });
+    let parse = |text: &str| Trace::parse(&format!("order little\npointer 4\n{}", text)).map(|_| ());
+    assert_eq!(Trace::parse("r 0x1000 1 00").map(|_| ()), invalid(1, "expected the byte order and pointer size first"));
+    assert_eq!(Trace::parse("order middle").map(|_| ()), invalid(1, "byte order should be \"little\" or \"big\""));
+    assert_eq!(Trace::parse("pointer 2").map(|_| ()), invalid(1, "pointer size should be 4 or 8"));
+    assert_eq!(parse("x 0x1000 1 00"), invalid(3, "expected \"r\" or \"w\""));
+    assert_eq!(parse("r 0x1000"), invalid(3, "expected \"r address length bytes\" or \"w address bytes result\""));
+    assert_eq!(parse("r zz 1 00"), invalid(3, "address is not a number"));
+    assert_eq!(parse("r 0x1000 one 00"), invalid(3, "length is not a number"));
+    assert_eq!(parse("r 0x1000 1 0g"), invalid(3, "bytes are not hex"));
+    assert_eq!(parse("r 0x1000 1 0000"), invalid(3, "more bytes read than asked for"));
+    assert_eq!(parse("r 0x1000 1"), invalid(3, "expected a result"));
+    assert_eq!(parse("r 0x1000 1 error x"), invalid(3, "error code is not a number"));
+    assert_eq!(parse("w 0x1000 00 1 1"), invalid(3, "too many fields"));
+}
@@ -0,0 +1,184 @@
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_structures::{bams_to_degrees, degrees_to_bams, Rotation, Vec3};
+use onvars_tool::state_file::StateReader;
+use onvars_tool::{parse_number, read_pointer, FixedSize, GameMemory, SaveStateable, Strided};
+
+const BASE: u64 = 0x0300_0000;
+
+// Saves from BASE, loads into freshly zeroed memory and checks both the
+// bytes written and the serialized form come back the same.
+fn round_trip<T: SaveStateable>(mut value: T, mut empty: T, bytes: &[u8]) {
+    let mem = FakeMemory::new();
+    mem.seed(BASE, bytes);
+    value.save(&mem, BASE).unwrap();
+
+    let mut data = Vec::new();
+    value.write_state(&mut data);
+    let mut reader = StateReader::new(&data);
+    empty.read_state(&mut reader).unwrap();
+    assert!(reader.is_empty());
+
+    mem.seed(BASE, &vec![0; bytes.len()]);
+    empty.load(&mem, BASE).unwrap();
+    assert_eq!(mem.bytes(BASE, bytes.len()), bytes);
+}
+
+#[test]
+fn primitives_are_little_endian() {
+    let mem = FakeMemory::new();
+    mem.seed(BASE, &[0x78, 0x56, 0x34, 0x12, 0xf0, 0xde, 0xbc, 0x9a]);
+
+    let mut a = 0u16;
+    a.save(&mem, BASE).unwrap();
+    assert_eq!(a, 0x5678);
+    let mut b = 0i32;
+    b.save(&mem, BASE + 4).unwrap();
+    assert_eq!(b, 0x9abc_def0u32 as i32);
+    let mut c = 0u64;
+    c.save(&mem, BASE).unwrap();
+    assert_eq!(c, 0x9abc_def0_1234_5678);
+    let mut d = 0i8;
+    d.save(&mem, BASE + 7).unwrap();
+    assert_eq!(d, -0x66);
+
+    1.5f32.load(&mem, BASE).unwrap();
+    assert_eq!(mem.bytes(BASE, 4), 1.5f32.to_le_bytes().to_vec());
+    (-2.25f64).load(&mem, BASE).unwrap();
+    assert_eq!(mem.bytes(BASE, 8), (-2.25f64).to_le_bytes().to_vec());
+}
+
+#[test]
+fn primitive_round_trips() {
+    round_trip(0i16, 0i16, &[0x34, 0x92]);
+    round_trip(0f32, 0f32, &0.75f32.to_le_bytes());
+    round_trip(0f64, 0f64, &1e10f64.to_le_bytes());
+    round_trip(0u64, 0u64, &[1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[test]
+fn arrays_are_packed() {
+    let bytes: Vec<u8> = (0..12).collect();
+    round_trip([0u16; 6], [0u16; 6], &bytes);
+    round_trip([[0u8; 3]; 4], [[0u8; 3]; 4], &bytes);
+    assert_eq!(<[u16; 6]>::SIZE, 12);
+    assert_eq!(<[[u32; 2]; 3]>::SIZE, 24);
+}
+
+#[test]
+fn byte_arrays_are_one_transfer() {
+    let mem = FakeMemory::new();
+    let mut bytes = [0u8; 0x10];
+    let err = bytes.save(&mem, BASE).unwrap_err();
+    assert_eq!(err, Error::Access {
+        kind: AccessKind::Read,
+        address: BASE,
+        requested: 0x10,
+        code: None,
+    });
+}
+
+#[test]
+fn strided_arrays_skip_the_gaps() {
+    let mem = FakeMemory::new();
+    mem.seed(BASE, &[1, 0, 0xaa, 0xaa, 2, 0, 0xaa, 0xaa, 3, 0, 0xaa, 0xaa]);
+
+    let mut items: Strided<u16, 3, 4> = Strided::new([0; 3]);
+    items.save(&mem, BASE).unwrap();
+    assert_eq!(items.0, [1, 2, 3]);
+
+    let mut data = Vec::new();
+    items.write_state(&mut data);
+    assert_eq!(data, vec![1, 0, 2, 0, 3, 0]);
+
+    items.0 = [4, 5, 6];
+    items.load(&mem, BASE).unwrap();
+    assert_eq!(mem.bytes(BASE, 12), vec![4, 0, 0xaa, 0xaa, 5, 0, 0xaa, 0xaa, 6, 0, 0xaa, 0xaa]);
+    assert_eq!(<Strided<u16, 3, 4>>::SIZE, 12);
+}
+
+#[test]
+fn tuples_are_laid_out_like_c_structs() {
+    let mem = FakeMemory::new();
+    mem.seed(BASE, &[1, 0xaa, 2, 0, 3, 0, 0, 0]);
+
+    let mut value = (0u8, 0u16, 0u32);
+    value.save(&mem, BASE).unwrap();
+    assert_eq!(value, (1, 2, 3));
+    assert_eq!(<(u8, u16, u32)>::SIZE, 8);
+    assert_eq!(<(u8, u16, u32)>::ALIGN, 4);
+    // Padded at the end to the u32's alignment.
+    assert_eq!(<(u32, u8)>::SIZE, 8);
+    assert_eq!(<[(u32, u8); 2]>::SIZE, 16);
+    assert_eq!(<(u8, Vec3)>::SIZE, 0x10);
+
+    round_trip((0u8, 0i32), (0u8, 0i32), &[9, 0, 0, 0, 1, 2, 3, 4]);
+    round_trip((0u8, 0u8, 0u8, [0u8; 2]), (0u8, 0u8, 0u8, [0u8; 2]), &[1, 2, 3, 4, 5]);
+    round_trip((0u16, 0u8, 0u16), (0u16, 0u8, 0u16), &[1, 2, 3, 0, 4, 5]);
+}
+
+#[test]
+fn vec3_and_rotation_layouts() {
+    let mem = FakeMemory::new();
+    let mut bytes = Vec::new();
+    for &f in [1.0f32, -2.5, 100.0].iter() {
+        bytes.extend_from_slice(&f.to_le_bytes());
+    }
+    for &angle in [0x4000i32, -0x8000, 0x10000].iter() {
+        bytes.extend_from_slice(&angle.to_le_bytes());
+    }
+    mem.seed(BASE, &bytes);
+
+    let mut value = (Vec3::default(), Rotation::default());
+    value.save(&mem, BASE).unwrap();
+    assert_eq!(value.0, Vec3::new(1.0, -2.5, 100.0));
+    assert_eq!(value.1, Rotation::new(0x4000, -0x8000, 0x10000));
+
+    round_trip(value, Default::default(), &bytes);
+}
+
+#[test]
+fn bams_conversions() {
+    assert_eq!(bams_to_degrees(0x4000), 90.0);
+    assert_eq!(bams_to_degrees(-0x8000), -180.0);
+    assert_eq!(degrees_to_bams(45.0), 0x2000);
+    assert_eq!(degrees_to_bams(360.0), 0x10000);
+}
+
+// A 64-bit game.
+struct Wide(FakeMemory);
+
+impl GameMemory for Wide {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        self.0.read_data(address, buf)
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        self.0.write_data(address, buf)
+    }
+
+    fn pointer_size(&self) -> usize {
+        8
+    }
+}
+
+#[test]
+fn pointers_use_the_backend_width() {
+    let mem = FakeMemory::new();
+    mem.seed(BASE, &0x1_2345_6789u64.to_le_bytes());
+    assert_eq!(read_pointer(&mem, BASE).unwrap(), 0x2345_6789);
+    let wide = Wide(mem);
+    assert_eq!(read_pointer(&wide, BASE).unwrap(), 0x1_2345_6789);
+
+    wide.0.seed(BASE, &[0; 8]);
+    assert_eq!(read_pointer(&wide, BASE).unwrap_err(), Error::NullPointer { address: BASE });
+}
+
+#[test]
+fn numbers_are_decimal_or_hex() {
+    assert_eq!(parse_number("1234"), Some(1234));
+    assert_eq!(parse_number("0x1234"), Some(0x1234));
+    assert_eq!(parse_number("0XaBc"), Some(0xabc));
+    assert_eq!(parse_number("12ab"), None);
+    assert_eq!(parse_number("0x"), None);
+}
@@ -0,0 +1,353 @@
+use onvars_tool::addresses::Addresses;
+use onvars_tool::error::{AccessKind, Error};
+use onvars_tool::fake_memory::FakeMemory;
+use onvars_tool::sa2_units::{CameraUnit, CharacterUnit, CounterToggles, CountersUnit, GravityUnit, RngUnit, TimeUnit};
+use onvars_tool::{GameMemory, SaveStateUnit};
+
+mod common;
+use common::*;
+
+fn round_trip_character(character_id: u8) {
+    let mem = FakeMemory::new();
+    seed_character(&mem, character_id, 1);
+    let regions = character_regions(character_id);
+    let before = snapshot(&mem, &regions);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_character(&mem, character_id, 50);
+    assert_ne!(snapshot(&mem, &regions), before);
+
+    unit.load(&mem).unwrap();
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn character_round_trip_speed() {
+    round_trip_character(0);
+    round_trip_character(1);
+}
+
+#[test]
+fn character_round_trip_hunt() {
+    round_trip_character(4);
+    round_trip_character(5);
+}
+
+#[test]
+fn character_round_trip_mech() {
+    round_trip_character(6);
+    round_trip_character(7);
+}
+
+#[test]
+fn character_round_trip_super() {
+    round_trip_character(9);
+    round_trip_character(0xa);
+}
+
+#[test]
+fn character_round_trip_mechless() {
+    round_trip_character(2);
+    round_trip_character(3);
+}
+
+#[test]
+fn character_round_trip_2p_characters() {
+    for &character_id in [8, 0xc, 0xd, 0xe, 0xf, 0x10].iter() {
+        round_trip_character(character_id);
+    }
+}
+
+#[test]
+fn super_sonic_load_only_writes_its_struct() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 9, 1);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    mem.seed(PHYSICS + 0x1d8, &[0xee; 0x10]);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(PHYSICS + 0x1d8, 0x10), vec![0xee; 0x10]);
+}
+
+#[test]
+fn super_sonic_and_super_shadow_share_a_layout() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 9, 1);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+    seed_character(&mem, 0xa, 2);
+    unit.load(&mem).unwrap();
+
+    seed_character(&mem, 2, 3);
+    let err = unit.load(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::CharacterMismatch { saved: 9, current: 2 });
+}
+
+#[test]
+fn character_load_follows_current_pointers() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 0, 1);
+    let before = mem.bytes(GLOBAL_METRICS, 0x40);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    // Move the global metric struct somewhere else, like a respawned task would.
+    let moved = 0x0320_0000;
+    mem.seed(moved, &pattern(7, 0x40));
+    mem.seed_u32(TASK + 0x38, moved as u32);
+
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(moved, 0x40), before);
+}
+
+#[test]
+fn character_load_rejects_different_character() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 0, 1);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_character(&mem, 4, 2);
+    let physics = mem.bytes(PHYSICS, 0x420);
+    let err = unit.load(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::CharacterMismatch { saved: 0, current: 4 });
+    assert_eq!(err.path(), &["CharacterUnit", "phs", "data"]);
+    assert_eq!(mem.bytes(PHYSICS, 0x420), physics);
+}
+
+#[test]
+fn character_save_rejects_unsupported_character() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 0xb, 1);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::UnsupportedCharacter { character_id: 0xb });
+}
+
+#[test]
+fn character_null_pointer_is_an_error() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(CHARACTER_PTR, 0);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::NullPointer { address: CHARACTER_PTR });
+    assert_eq!(err.to_string(), "CharacterUnit: null pointer at 0x01dea6e0");
+    assert!(unit.load(&mem).is_err());
+}
+
+#[test]
+fn nested_null_pointer_reports_path() {
+    let mem = FakeMemory::new();
+    seed_character(&mem, 0, 1);
+    mem.seed_u32(ACTION + 0x2c, 0);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::NullPointer { address: ACTION + 0x2c });
+    assert_eq!(err.path(), &["CharacterUnit", "acs", "collision_data"]);
+}
+
+#[test]
+fn camera_round_trip() {
+    let mem = FakeMemory::new();
+    seed_camera(&mem, 1);
+    let regions = camera_regions();
+    let before = snapshot(&mem, &regions);
+
+    let mut unit = CameraUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    seed_camera(&mem, 50);
+    assert_ne!(snapshot(&mem, &regions), before);
+
+    unit.load(&mem).unwrap();
+    assert_eq!(snapshot(&mem, &regions), before);
+}
+
+#[test]
+fn time_round_trip() {
+    let mem = FakeMemory::new();
+    mem.seed(TIME - 1, &[0xaa, 1, 23, 45, 0xbb]);
+
+    let mut unit = TimeUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    mem.seed(TIME, &[9, 59, 99]);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(TIME - 1, 5), vec![0xaa, 1, 23, 45, 0xbb]);
+}
+
+#[test]
+fn gravity_round_trip() {
+    let mem = FakeMemory::new();
+    let gravity = pattern(3, 0xc);
+    mem.seed(GRAVITY, &gravity);
+
+    let mut unit = GravityUnit::new(&Addresses::default());
+    unit.save(&mem).unwrap();
+
+    mem.seed(GRAVITY, &pattern(4, 0xc));
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(GRAVITY, 0xc), gravity);
+}
+
+fn seed_counters(mem: &FakeMemory, rings: i16, lives: i8, score: u32) {
+    mem.seed(LIVES, &[lives as u8, 0x22]);
+    mem.seed(RINGS, &rings.to_le_bytes());
+    mem.seed(RINGS + 2, &7i16.to_le_bytes());
+    mem.seed_u32(SCORE, score);
+}
+
+#[test]
+fn counters_round_trip() {
+    let mem = FakeMemory::new();
+    seed_counters(&mem, 123, 5, 4560);
+    let before = mem.bytes(LIVES, 8);
+    let score = mem.bytes(SCORE, 4);
+
+    let mut unit = CountersUnit::new(&Addresses::default(), CounterToggles::all());
+    unit.save(&mem).unwrap();
+    seed_counters(&mem, 0, 1, 99);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(LIVES, 8), before);
+    assert_eq!(mem.bytes(SCORE, 4), score);
+}
+
+#[test]
+fn counters_toggles_skip_values_on_load() {
+    let mem = FakeMemory::new();
+    seed_counters(&mem, 123, 5, 4560);
+
+    let mut unit = CountersUnit::new(&Addresses::default(), CounterToggles {
+        rings: false,
+        lives: true,
+        score: false,
+    });
+    unit.save(&mem).unwrap();
+    seed_counters(&mem, 0, 1, 99);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.bytes(RINGS, 2), 0i16.to_le_bytes().to_vec());
+    assert_eq!(mem.bytes(LIVES, 1), vec![5]);
+    assert_eq!(mem.bytes(SCORE, 4), 99u32.to_le_bytes().to_vec());
+
+    // Still saved, so the file has everything.
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data.len(), 4 + 2 + 4);
+    assert_eq!(&data[..2], &123i16.to_le_bytes());
+}
+
+#[test]
+fn rng_round_trip() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(RNG_SEED, 0x1234_5678);
+
+    let mut unit = RngUnit::new(&Addresses::default(), None);
+    unit.save(&mem).unwrap();
+    mem.seed_u32(RNG_SEED, 42);
+    unit.load(&mem).unwrap();
+    assert_eq!(mem.read_u32(RNG_SEED).unwrap(), 0x1234_5678);
+}
+
+#[test]
+fn locked_rng_writes_the_lock_seed() {
+    let mem = FakeMemory::new();
+    mem.seed_u32(RNG_SEED, 0x1234_5678);
+
+    let mut unit = RngUnit::new(&Addresses::default(), Some(7));
+    unit.save(&mem).unwrap();
+    for _ in 0..2 {
+        mem.seed_u32(RNG_SEED, 42);
+        unit.load(&mem).unwrap();
+        assert_eq!(mem.read_u32(RNG_SEED).unwrap(), 7);
+    }
+
+    // The real seed still goes in the file.
+    let mut data = Vec::new();
+    unit.write_state(&mut data);
+    assert_eq!(data, 0x1234_5678u32.to_le_bytes().to_vec());
+}
+
+#[test]
+fn unmapped_memory_is_an_error() {
+    let mem = FakeMemory::new();
+
+    let mut unit = GravityUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::Access {
+        kind: AccessKind::Read,
+        address: GRAVITY,
+        requested: 0xc,
+        code: None,
+    });
+    assert_eq!(err.path(), &["GravityUnit"]);
+    assert!(unit.load(&mem).is_err());
+}
+
+// Pretends the process went away halfway through every transfer.
+struct ShortMemory(FakeMemory);
+
+impl GameMemory for ShortMemory {
+    fn read_data(&self, address: u64, buf: &mut [u8]) -> Result<usize, Error> {
+        let half = buf.len() / 2;
+        self.0.read_data(address, &mut buf[..half])
+    }
+
+    fn write_data(&self, address: u64, buf: &[u8]) -> Result<usize, Error> {
+        let half = buf.len() / 2;
+        self.0.write_data(address, &buf[..half])
+    }
+}
+
+#[test]
+fn partial_transfers_are_errors() {
+    let mem = ShortMemory(FakeMemory::new());
+    mem.0.seed(GRAVITY, &pattern(3, 0xc));
+
+    assert_eq!(mem.read_u32(GRAVITY).unwrap_err(), Error::PartialAccess {
+        kind: AccessKind::Read,
+        address: GRAVITY,
+        requested: 4,
+        transferred: 2,
+    });
+    assert!(mem.read_u8(GRAVITY).is_err());
+    assert!(mem.write_u32(GRAVITY, 0).is_err());
+
+    let mut unit = GravityUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::PartialAccess {
+        kind: AccessKind::Read,
+        address: GRAVITY,
+        requested: 0xc,
+        transferred: 0x6,
+    });
+    let err = unit.load(&mem).unwrap_err();
+    assert_eq!(err.root(), &Error::PartialAccess {
+        kind: AccessKind::Write,
+        address: GRAVITY,
+        requested: 0xc,
+        transferred: 0x6,
+    });
+}
+
+#[test]
+fn partial_read_while_following_pointers_is_an_error() {
+    let mem = ShortMemory(FakeMemory::new());
+    seed_character(&mem.0, 0, 1);
+
+    let mut unit = CharacterUnit::new(&Addresses::default());
+    let err = unit.save(&mem).unwrap_err();
+    assert_eq!(err.path(), &["CharacterUnit"]);
+    match *err.root() {
+        Error::PartialAccess { kind: AccessKind::Read, address: CHARACTER_PTR, .. } => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+}